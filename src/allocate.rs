@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Strategies for choosing the addresses to propose within a subnet
+
+use crate::random::Random;
+use crate::range::Range;
+
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+/// Proposes candidate addresses, which the caller checks for availability
+pub trait Allocator {
+    fn propose(&mut self) -> IpAddr;
+}
+
+/// How to choose the addresses within a subnet
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Random addresses throughout the subnet
+    Random,
+
+    /// The lowest free addresses, packing the subnet
+    Sequential,
+
+    /// Addresses starting from one derived from the user, so the same user
+    /// tends to get the same address
+    Hash,
+}
+
+impl std::fmt::Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Random => write!(f, "random"),
+            Self::Sequential => write!(f, "sequential"),
+            Self::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Self::Random),
+            "sequential" => Ok(Self::Sequential),
+            "hash" => Ok(Self::Hash),
+            _ => Err(format!("invalid allocation strategy: {}", s)),
+        }
+    }
+}
+
+impl Strategy {
+    /// Creates an allocator proposing addresses from `pool` for `uid`
+    pub fn allocator<'a>(
+        self,
+        pool: Range,
+        random: &'a mut Random,
+        uid: u32,
+    ) -> Box<dyn Allocator + 'a> {
+        match self {
+            Self::Random => Box::new(Randomly { pool, random }),
+            Self::Sequential => Box::new(Sequentially { pool, next: 0 }),
+            Self::Hash => {
+                let mut hasher = Sha256::new();
+                hasher.update(pool.to_string());
+                hasher.update(uid.to_be_bytes());
+
+                let mut start = [0u8; 16];
+                start.copy_from_slice(&hasher.finalize()[..16]);
+                let next = u128::from_be_bytes(start);
+                Box::new(Sequentially { pool, next })
+            }
+        }
+    }
+}
+
+struct Randomly<'a> {
+    pool: Range,
+    random: &'a mut Random,
+}
+
+impl Allocator for Randomly<'_> {
+    fn propose(&mut self) -> IpAddr {
+        self.pool.random(self.random.next_u128())
+    }
+}
+
+/// Proposes consecutive addresses (wrapping around) from an offset
+struct Sequentially {
+    pool: Range,
+    next: u128,
+}
+
+impl Allocator for Sequentially {
+    fn propose(&mut self) -> IpAddr {
+        let proposed = self.pool.random(self.next);
+        self.next = self.next.wrapping_add(1);
+        proposed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::ip;
+
+    fn pool() -> Range {
+        "10.0.0.10-10.0.0.13".parse().unwrap()
+    }
+
+    fn proposals(strategy: Strategy, uid: u32, count: usize) -> Vec<IpAddr> {
+        let mut random = Random::new(0);
+        let mut allocator = strategy.allocator(pool(), &mut random, uid);
+        (0..count).map(|_| allocator.propose()).collect()
+    }
+
+    #[test]
+    fn from_str() {
+        for strategy in &["random", "sequential", "hash"] {
+            assert_eq!(strategy.parse::<Strategy>().unwrap().to_string(), *strategy);
+        }
+
+        assert!("lowest".parse::<Strategy>().is_err());
+    }
+
+    #[test]
+    fn sequential() {
+        let proposed = proposals(Strategy::Sequential, 1000, 5);
+        let expected: Vec<IpAddr> = (10..=13)
+            .chain(Some(10))
+            .map(|n| ip(&format!("10.0.0.{}", n)))
+            .collect();
+        assert_eq!(proposed, expected);
+    }
+
+    #[test]
+    fn hash() {
+        let proposed = proposals(Strategy::Hash, 1000, 4);
+        assert_eq!(proposed, proposals(Strategy::Hash, 1000, 4));
+        assert!(proposed.iter().all(|a| pool().contains(*a)));
+
+        // A lap covers the whole pool.
+        let mut sorted = proposed;
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 4);
+    }
+
+    #[test]
+    fn random() {
+        let proposed = proposals(Strategy::Random, 1000, 100);
+        assert!(proposed.iter().all(|a| pool().contains(*a)));
+    }
+}
@@ -0,0 +1,1422 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::allocate::Strategy;
+use crate::error::{Context, Error::Insecure};
+use crate::mac::Policy;
+use crate::netlink::{IpvlanMode, Offload, Route, Subnet};
+use crate::nft::Ruleset;
+use crate::program::Program;
+use crate::range::Range;
+use crate::sysctl::Sysctl;
+
+use std::collections::HashMap;
+use std::fs::{read_dir, File, Metadata};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::net::IpAddr;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::{debug, info};
+use serde::Deserialize;
+
+/// Per-subnet settings from the configuration file
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    /// The ipvlan mode of the interface carrying the subnet
+    pub mode: Option<IpvlanMode>,
+
+    /// The parent interface (instead of the one with an address within it)
+    pub parent: Option<String>,
+
+    /// The 802.1Q VLAN of the parent interface to build upon
+    pub vlan: Option<u16>,
+
+    /// The default gateway (instead of the host's address within it)
+    pub gateway: Option<IpAddr>,
+
+    /// The MTU of the interface carrying the subnet
+    pub mtu: Option<u32>,
+
+    /// The hardware address policy of a macvlan carrying the subnet (a fixed
+    /// address requires the subnet to hold a single address)
+    pub mac: Option<Policy>,
+
+    /// The DNS servers for commands using the subnet
+    pub dns: Vec<IpAddr>,
+
+    /// The DNS search domains for commands using the subnet
+    pub search: Vec<String>,
+
+    /// The number of addresses to assign from the subnet
+    pub count: Option<usize>,
+
+    /// The addresses never to assign from the subnet
+    pub exclude: Vec<Range>,
+
+    /// The only addresses to assign from the subnet (instead of all of them)
+    pub range: Option<Range>,
+
+    /// Whether to skip the default route through the gateway
+    pub no_gateway: bool,
+
+    /// The users and groups allowed to use the subnet (or everyone if empty)
+    pub allow: Vec<Principal>,
+
+    /// How long a released address stays leased to the user who held it
+    pub lease: Option<Duration>,
+
+    /// How long a released address isn't assigned to anyone
+    pub grace: Option<Duration>,
+
+    /// How to choose the addresses to assign
+    pub strategy: Option<Strategy>,
+
+    /// The metric of the default route through the gateway
+    pub metric: Option<u32>,
+
+    /// How long addresses from the subnet stay valid before the kernel
+    /// removes them (or forever)
+    pub lifetime: Option<Duration>,
+
+    /// The offloads to turn off on interfaces carrying the subnet
+    pub disable: Vec<Offload>,
+
+    /// The bandwidth cap (in bits per second) of interfaces carrying the
+    /// subnet
+    pub rate: Option<u64>,
+}
+
+impl Settings {
+    /// Checks the settings for consistency with their subnet
+    fn validate(&self, subnet: &Subnet) -> Result<()> {
+        if let Some(gateway) = self.gateway {
+            if !subnet.contains(gateway) {
+                return Err(invalid(format!("{} is not within {}", gateway, subnet)));
+            }
+        }
+
+        if !self.search.is_empty() && self.dns.is_empty() {
+            return Err(invalid(format!(
+                "search domains require dns servers for {}",
+                subnet
+            )));
+        }
+
+        if let Some(vlan) = self.vlan {
+            if vlan == 0 || vlan > 4094 {
+                return Err(invalid(format!("invalid vlan {} for {}", vlan, subnet)));
+            }
+
+            if self.parent.is_none() || self.gateway.is_none() {
+                return Err(invalid(format!(
+                    "vlan requires a parent and gateway for {}",
+                    subnet
+                )));
+            }
+        }
+
+        // The smallest MTUs the kernel accepts for each family
+        if let Some(mtu) = self.mtu {
+            let min = match subnet.address() {
+                IpAddr::V4(..) => 68,
+                IpAddr::V6(..) => 1280,
+            };
+
+            if mtu < min {
+                return Err(invalid(format!("mtu {} is too small for {}", mtu, subnet)));
+            }
+        }
+
+        for range in self.exclude.iter().chain(&self.range) {
+            if !subnet.contains(range.start()) || !subnet.contains(range.end()) {
+                return Err(invalid(format!("{} is not within {}", range, subnet)));
+            }
+        }
+
+        // Every address assigned must be distinct, so the count is limited
+        // by the addresses available.
+        let bits = match subnet.address() {
+            IpAddr::V4(..) => 32,
+            IpAddr::V6(..) => 128,
+        };
+        let size = match self.range {
+            Some(range) => range.size(),
+            None => 1u128
+                .checked_shl(bits - u32::from(subnet.prefix()))
+                .unwrap_or(u128::MAX),
+        };
+
+        if let Some(count) = self.count {
+            if count == 0 || count as u128 > size {
+                return Err(invalid(format!("invalid count {} for {}", count, subnet)));
+            }
+        }
+
+        // A fixed hardware address may only be used by one interface at a
+        // time, which holds the subnet's only address.
+        if let Some(Policy::Fixed(..)) = self.mac {
+            if size != 1 || self.count.unwrap_or(1) != 1 {
+                return Err(invalid(format!(
+                    "a fixed mac requires a single address for {}",
+                    subnet
+                )));
+            }
+        }
+
+        // The token bucket works in bytes per second.
+        if let Some(rate) = self.rate {
+            if rate < 8 {
+                return Err(invalid(format!("rate {} is too low for {}", rate, subnet)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Overrides these settings with those set in `other`
+    pub fn merge(&mut self, other: Settings) {
+        if other.mode.is_some() {
+            self.mode = other.mode;
+        }
+
+        if other.parent.is_some() {
+            self.parent = other.parent;
+        }
+
+        if other.gateway.is_some() {
+            self.gateway = other.gateway;
+        }
+
+        if other.vlan.is_some() {
+            self.vlan = other.vlan;
+        }
+
+        if other.mtu.is_some() {
+            self.mtu = other.mtu;
+        }
+
+        if other.mac.is_some() {
+            self.mac = other.mac;
+        }
+
+        if !other.dns.is_empty() {
+            self.dns = other.dns;
+        }
+
+        if !other.search.is_empty() {
+            self.search = other.search;
+        }
+
+        if other.count.is_some() {
+            self.count = other.count;
+        }
+
+        if other.range.is_some() {
+            self.range = other.range;
+        }
+
+        if other.no_gateway {
+            self.no_gateway = true;
+        }
+
+        if other.lease.is_some() {
+            self.lease = other.lease;
+        }
+
+        if other.grace.is_some() {
+            self.grace = other.grace;
+        }
+
+        if other.strategy.is_some() {
+            self.strategy = other.strategy;
+        }
+
+        if other.metric.is_some() {
+            self.metric = other.metric;
+        }
+
+        if other.lifetime.is_some() {
+            self.lifetime = other.lifetime;
+        }
+
+        if !other.disable.is_empty() {
+            self.disable = other.disable;
+        }
+
+        if other.rate.is_some() {
+            self.rate = other.rate;
+        }
+
+        // Exclusions accumulate, since they protect addresses in use elsewhere.
+        for range in other.exclude {
+            if !self.exclude.contains(&range) {
+                self.exclude.push(range);
+            }
+        }
+
+        for principal in other.allow {
+            if !self.allow.contains(&principal) {
+                self.allow.push(principal);
+            }
+        }
+    }
+}
+
+/// A user or group granted access to a subnet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Principal {
+    User(String),
+    Group(String),
+}
+
+impl std::fmt::Display for Principal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User(name) => write!(f, "user:{}", name),
+            Self::Group(name) => write!(f, "group:{}", name),
+        }
+    }
+}
+
+impl std::str::FromStr for Principal {
+    type Err = String;
+
+    /// Parses `user:NAME` or `group:NAME`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+        match (split.next(), split.next()) {
+            (Some("user"), Some(name)) if !name.is_empty() => Ok(Self::User(name.into())),
+            (Some("group"), Some(name)) if !name.is_empty() => Ok(Self::Group(name.into())),
+            _ => Err(format!("invalid principal: {}", s)),
+        }
+    }
+}
+
+/// A structured (TOML or YAML) configuration file
+///
+/// ```toml
+/// loopback = ["127.0.0.53/8"]
+/// dummy = ["192.0.2.10/32"]
+/// sysctl = ["net.ipv4.ip_forward=1"]
+/// nftables = "/etc/ipvlan/egress.nft"
+/// routes = ["10.0.0.0/8@10.2.0.1"]
+/// env = ["REGION_DNS"]
+/// programs = ["/usr/bin/backup", "sha256:e3b0c442..."]
+/// no-scan = true
+/// command = ["/usr/bin/backup", "--daily"]
+/// lock-command = true
+///
+/// [reservations]
+/// "10.2.0.10" = "user:alice"
+///
+/// [subnets."10.2.0.100-10.2.0.199/24"]
+/// mode = "l2"
+/// parent = "eth1"
+/// vlan = 30
+/// gateway = "10.2.0.1"
+/// mtu = 9000
+/// mac = "stable"
+/// dns = ["${REGION_DNS}"]
+/// search = ["storage.example.com"]
+/// count = 2
+/// exclude = ["10.2.0.1-10.2.0.50"]
+/// no-gateway = true
+/// allow = ["group:netusers"]
+/// lease = 86400
+/// grace = 60
+/// strategy = "sequential"
+/// metric = 100
+/// lifetime = 86400
+/// disable = ["gro", "tso"]
+/// rate = 100000000
+///
+/// [profiles.staging.subnets."10.3.0.0/24"]
+/// gateway = "10.3.0.1"
+/// dns = ["10.3.0.53"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Document {
+    loopback: Vec<String>,
+    dummy: Vec<String>,
+    sysctl: Vec<String>,
+    nftables: Option<PathBuf>,
+    #[serde(rename = "nftables-ruleset")]
+    nftables_ruleset: Option<String>,
+    routes: Vec<String>,
+    env: Vec<String>,
+    programs: Vec<String>,
+    reservations: HashMap<IpAddr, String>,
+    #[serde(rename = "no-scan")]
+    no_scan: bool,
+    command: Option<Vec<String>>,
+    #[serde(rename = "lock-command")]
+    lock_command: bool,
+    subnets: HashMap<String, Section>,
+    profiles: HashMap<String, Document>,
+}
+
+/// The settings of a subnet in a structured configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Section {
+    mode: Option<String>,
+    parent: Option<String>,
+    vlan: Option<u16>,
+    gateway: Option<IpAddr>,
+    mtu: Option<u32>,
+    mac: Option<String>,
+    dns: Vec<String>,
+    search: Vec<String>,
+    count: Option<usize>,
+    exclude: Vec<String>,
+    #[serde(rename = "no-gateway")]
+    no_gateway: bool,
+    allow: Vec<String>,
+    lease: Option<u64>,
+    grace: Option<u64>,
+    strategy: Option<String>,
+    metric: Option<u32>,
+    lifetime: Option<u64>,
+    disable: Vec<String>,
+    rate: Option<u64>,
+}
+
+impl Document {
+    /// Converts the document into a configuration, expanding the variables
+    /// in `allowed` (see `interpolate()`)
+    fn config(self, allowed: &[String]) -> Result<Config> {
+        let document = self;
+        let mut config = Config::default();
+
+        for loopback in &document.loopback {
+            config.loopback.push(address(loopback)?);
+        }
+
+        for dummy in &document.dummy {
+            config.dummy.push(address(dummy)?);
+        }
+
+        for sysctl in &document.sysctl {
+            config.set(sysctl.parse().map_err(invalid)?);
+        }
+
+        config.nftables = match (document.nftables, document.nftables_ruleset) {
+            (Some(..), Some(..)) => {
+                return Err(invalid("nftables and nftables-ruleset conflict".into()))
+            }
+            (Some(path), None) => Some(ruleset(path)?),
+            (None, Some(ruleset)) => Some(Ruleset::Inline(ruleset)),
+            (None, None) => None,
+        };
+
+        for route in &document.routes {
+            config.routes.push(route.parse().map_err(invalid)?);
+        }
+
+        for name in &document.env {
+            variable(name)?;
+        }
+
+        for program in &document.programs {
+            config.programs.push(program.parse().map_err(invalid)?);
+        }
+
+        config.no_scan = document.no_scan;
+        config.lock_command = document.lock_command;
+        config.command = match document.command {
+            Some(argv) if argv.is_empty() => return Err(invalid("empty command".into())),
+            command => command,
+        };
+
+        for (address, owner) in &document.reservations {
+            let reservation = Reservation::parse(*address, owner)?;
+            config.reservations.push(reservation);
+        }
+
+        for (subnet, section) in document.subnets {
+            let (subnet, range) = self::subnet(&subnet)?;
+            let settings = Settings {
+                mode: section
+                    .mode
+                    .map(|m| m.parse().map_err(invalid))
+                    .transpose()?,
+                parent: section
+                    .parent
+                    .map(|p| interpolate(&p, allowed))
+                    .transpose()?,
+                vlan: section.vlan,
+                gateway: section.gateway,
+                mtu: section.mtu,
+                mac: section
+                    .mac
+                    .map(|m| m.parse().map_err(invalid))
+                    .transpose()?,
+                dns: section
+                    .dns
+                    .iter()
+                    .map(|d| {
+                        let dns = interpolate(d, allowed)?;
+                        dns.parse::<IpAddr>()
+                            .map_err(|e| invalid(format!("invalid dns server '{}': {}", dns, e)))
+                    })
+                    .collect::<Result<_>>()?,
+                search: section.search,
+                count: section.count,
+                exclude: section
+                    .exclude
+                    .iter()
+                    .map(|r| r.parse().map_err(invalid))
+                    .collect::<Result<_>>()?,
+                range,
+                no_gateway: section.no_gateway,
+                allow: section
+                    .allow
+                    .iter()
+                    .map(|p| p.parse().map_err(invalid))
+                    .collect::<Result<_>>()?,
+                lease: section.lease.map(Duration::from_secs),
+                grace: section.grace.map(Duration::from_secs),
+                strategy: section
+                    .strategy
+                    .map(|s| s.parse().map_err(invalid))
+                    .transpose()?,
+                metric: section.metric,
+                lifetime: section.lifetime.map(Duration::from_secs),
+                disable: section
+                    .disable
+                    .iter()
+                    .map(|o| o.parse().map_err(invalid))
+                    .collect::<Result<_>>()?,
+                rate: section.rate,
+            };
+
+            settings.validate(&subnet)?;
+            debug!("loaded subnet: subnet={} settings={:?}", subnet, settings);
+            config.subnets.insert(subnet, settings);
+        }
+
+        for (name, profile) in document.profiles {
+            if !profile.profiles.is_empty() {
+                return Err(invalid(format!("profile {} can't contain profiles", name)));
+            }
+
+            config.profiles.insert(name, profile.config(allowed)?);
+        }
+
+        Ok(config)
+    }
+}
+
+/// An address reserved for a user
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reservation {
+    pub address: IpAddr,
+
+    /// The name (or uid) of the user
+    pub user: String,
+}
+
+impl Reservation {
+    /// Parses an owner (`user:NAME`) for `address`
+    fn parse(address: IpAddr, owner: &str) -> Result<Self> {
+        match owner.strip_prefix("user:") {
+            Some(user) if !user.is_empty() => Ok(Self {
+                address,
+                user: user.into(),
+            }),
+            _ => Err(invalid(format!("invalid reservation owner: {}", owner))),
+        }
+    }
+}
+
+/// The configuration
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// The subnets to assign addresses from and their settings
+    pub subnets: HashMap<Subnet, Settings>,
+
+    /// Additional addresses (and prefixes) for the loopback interface
+    pub loopback: Vec<(IpAddr, u8)>,
+
+    /// Addresses (and prefixes) for a dummy interface, such as service VIPs
+    pub dummy: Vec<(IpAddr, u8)>,
+
+    /// Network sysctl settings for the namespace
+    pub sysctl: Vec<Sysctl>,
+
+    /// The nftables ruleset to load in the namespace
+    pub nftables: Option<Ruleset>,
+
+    /// Additional routes to install in the namespace
+    pub routes: Vec<Route>,
+
+    /// Addresses reserved for specific users
+    pub reservations: Vec<Reservation>,
+
+    /// The programs which may be executed (or any if empty)
+    pub programs: Vec<Program>,
+
+    /// Whether to trust the records instead of scanning every namespace
+    pub no_scan: bool,
+
+    /// The command to execute when none is given
+    pub command: Option<Vec<String>>,
+
+    /// Whether only root may give a command other than `command`
+    pub lock_command: bool,
+
+    /// Named configurations merged in when selected (see `select()`)
+    pub profiles: HashMap<String, Config>,
+}
+
+impl Config {
+    /// Adds a sysctl setting, replacing any earlier one for the same key
+    fn set(&mut self, sysctl: Sysctl) {
+        self.sysctl.retain(|s| s.key() != sysctl.key());
+        self.sysctl.push(sysctl);
+    }
+
+    /// Merges `other` into this configuration; settings in `other` take precedence
+    pub fn merge(&mut self, other: Config) {
+        for (subnet, settings) in other.subnets {
+            self.subnets.entry(subnet).or_default().merge(settings);
+        }
+
+        for address in other.loopback {
+            if !self.loopback.contains(&address) {
+                self.loopback.push(address);
+            }
+        }
+
+        for address in other.dummy {
+            if !self.dummy.contains(&address) {
+                self.dummy.push(address);
+            }
+        }
+
+        for sysctl in other.sysctl {
+            self.set(sysctl);
+        }
+
+        if other.nftables.is_some() {
+            self.nftables = other.nftables;
+        }
+
+        for route in other.routes {
+            if !self.routes.contains(&route) {
+                self.routes.push(route);
+            }
+        }
+
+        for program in other.programs {
+            if !self.programs.contains(&program) {
+                self.programs.push(program);
+            }
+        }
+
+        for reservation in other.reservations {
+            self.reservations
+                .retain(|r| r.address != reservation.address);
+            self.reservations.push(reservation);
+        }
+
+        if other.no_scan {
+            self.no_scan = true;
+        }
+
+        if other.command.is_some() {
+            self.command = other.command;
+        }
+
+        if other.lock_command {
+            self.lock_command = true;
+        }
+
+        for (name, profile) in other.profiles {
+            self.profiles.entry(name).or_default().merge(profile);
+        }
+    }
+
+    /// Merges in the named profile (if any), dropping all the others
+    pub fn select(mut self, profile: Option<&str>) -> Result<Config> {
+        let mut profiles = std::mem::take(&mut self.profiles);
+
+        if let Some(name) = profile {
+            let profile = profiles
+                .remove(name)
+                .ok_or_else(|| invalid(format!("unknown profile: {}", name)))?;
+            debug!("selected profile: name={}", name);
+            self.merge(profile);
+        }
+
+        Ok(self)
+    }
+}
+
+/// The default configuration file
+pub const DEFAULT: &str = "/etc/ipvlan.conf";
+
+/// The directory of configuration snippets read after the default file
+pub const DEFAULT_DIR: &str = "/etc/ipvlan.conf.d";
+
+/// The path naming standard input
+pub const STDIN: &str = "-";
+
+/// The policy pinning the SHA-256 digests of configuration files
+///
+/// Each line holds a digest and the absolute path of the file it pins, as
+/// printed by `sha256sum`. If the policy exists, files it doesn't pin are
+/// refused.
+pub const POLICY: &str = "/etc/ipvlan.pins";
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// Validates configuration file (or directory) permissions
+fn validate(path: &Path, md: &Metadata) -> Result<()> {
+    //assert_eq!(md.dev(), File::open("/proc/self/exe")?.metadata()?.dev());
+    if md.uid() != 0 {
+        return Err(Insecure(path.into(), "must be owned by root").into());
+    }
+
+    let mut mode = md.mode();
+    mode &= 0o7777;
+    mode &= !0o0444; // Remove read bits
+    mode &= !0o0200; // Remove owner write bit.
+    if md.is_dir() {
+        mode &= !0o0111; // Remove search bits.
+    }
+    if mode != 0o0000 {
+        return Err(Insecure(path.into(), "must only be writable by its owner").into());
+    }
+
+    Ok(())
+}
+
+/// Expands `path` into the configuration files it names
+///
+/// A directory names every regular file within it, in name order.
+pub fn expand(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.into()]);
+    }
+
+    validate(
+        path,
+        &path.metadata().context(|| path.display().to_string())?,
+    )?;
+    let mut paths: Vec<PathBuf> = read_dir(path)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Opens, locks and validates a configuration file
+///
+/// Standard input can't be validated, so only root may use it.
+pub fn open(path: &Path, timeout: Option<Duration>) -> Result<File> {
+    info!("loading config: path={}", path.display());
+
+    if path == Path::new(STDIN) {
+        if unsafe { libc::getuid() } != 0 {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "only root may read the configuration from stdin",
+            ));
+        }
+
+        return match unsafe { libc::dup(libc::STDIN_FILENO) } {
+            -1 => Err(Error::last_os_error()),
+            fd => Ok(unsafe { File::from_raw_fd(fd) }),
+        };
+    }
+
+    let mut file = File::open(path).context(|| path.display().to_string())?;
+    crate::lock(&file, timeout)?;
+    validate(path, &file.metadata()?)?;
+    verify(path, &mut file)?;
+    Ok(file)
+}
+
+/// Checks a configuration file against the digest pinned by the policy
+fn verify(path: &Path, file: &mut File) -> Result<()> {
+    let policy = match File::open(POLICY) {
+        Ok(policy) => policy,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context(|| POLICY.into()),
+    };
+    validate(Path::new(POLICY), &policy.metadata()?)?;
+
+    let canonical = path.canonicalize()?;
+    let mut pinned = None;
+    for line in BufReader::new(policy).lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        if let (Some(digest), Some(pin)) = (fields.next(), fields.next()) {
+            if !digest.starts_with('#') && Path::new(pin) == canonical {
+                pinned = Some(digest.to_ascii_lowercase());
+            }
+        }
+    }
+
+    let pinned = pinned.ok_or_else(|| Insecure(path.into(), "is not pinned by the policy"))?;
+    let digest = crate::program::sha256(&mut *file)?;
+    file.seek(SeekFrom::Start(0))?;
+    if digest != pinned {
+        return Err(Insecure(path.into(), "does not match its pinned digest").into());
+    }
+
+    debug!("verified config: path={} digest={}", path.display(), digest);
+    Ok(())
+}
+
+/// Parses an address with an optional prefix (defaulting to a single host)
+fn address(s: &str) -> Result<(IpAddr, u8)> {
+    let err = || invalid(format!("invalid address: {}", s));
+
+    let mut split = s.splitn(2, '/');
+    let address: IpAddr = split
+        .next()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|_| err())?;
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match split.next() {
+        Some(prefix) => prefix.parse().map_err(|_| err())?,
+        None => max,
+    };
+
+    if prefix > max {
+        return Err(err());
+    }
+
+    Ok((address, prefix))
+}
+
+/// Checks the path of an nftables ruleset
+fn ruleset(path: PathBuf) -> Result<Ruleset> {
+    if !path.is_absolute() {
+        return Err(invalid(format!(
+            "ruleset path must be absolute: {}",
+            path.display()
+        )));
+    }
+
+    Ok(Ruleset::File(path))
+}
+
+/// Parses a subnet, optionally restricted to a range (`start-end/prefix`)
+fn subnet(s: &str) -> Result<(Subnet, Option<Range>)> {
+    if !s.contains('-') {
+        return Ok((s.parse()?, None));
+    }
+
+    // The prefix is checked against the family by parsing it with the start.
+    let mut split = s.splitn(2, '/');
+    let range: Range = split.next().unwrap_or_default().parse().map_err(invalid)?;
+    let prefix = split
+        .next()
+        .ok_or_else(|| invalid(format!("expected START-END/PREFIX: {}", s)))?;
+    let subnet: Subnet = format!("{}/{}", range.start(), prefix).parse()?;
+
+    Ok((subnet, Some(range)))
+}
+
+/// The format of a configuration file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One subnet per line
+    Line,
+
+    /// Structured TOML
+    Toml,
+
+    /// Structured YAML (requires the `yaml` feature)
+    Yaml,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Line => write!(f, "line"),
+            Self::Toml => write!(f, "toml"),
+            Self::Yaml => write!(f, "yaml"),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "line" => Self::Line,
+            "toml" => Self::Toml,
+            "yaml" => Self::Yaml,
+            _ => return Err(format!("invalid configuration format: {}", s)),
+        })
+    }
+}
+
+impl Format {
+    /// Chooses the format by the extension of `path`
+    pub fn detect(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::Toml,
+            Some("yaml") | Some("yml") => Self::Yaml,
+            _ => Self::Line,
+        }
+    }
+}
+
+/// The variables a configuration file allows to be interpolated
+#[derive(Debug, Default, Deserialize)]
+struct Variables {
+    #[serde(default)]
+    env: Vec<String>,
+}
+
+impl Format {
+    /// Finds the variables allowed (with `env`) by a configuration file
+    fn variables(self, text: &str) -> Result<Vec<String>> {
+        match self {
+            Self::Line => Ok(text
+                .lines()
+                .map(|line| fields(line, 0))
+                .filter(|fields| fields.first().map(|f| f.text) == Some("env"))
+                .flat_map(|fields| fields.into_iter().skip(1).map(|f| f.text.into()))
+                .collect()),
+
+            Self::Toml => toml::from_str::<Variables>(text)
+                .map(|v| v.env)
+                .map_err(|e| invalid(e.to_string())),
+
+            #[cfg(feature = "yaml")]
+            Self::Yaml => serde_yaml::from_str::<Variables>(text)
+                .map(|v| v.env)
+                .map_err(|e| invalid(e.to_string())),
+
+            #[cfg(not(feature = "yaml"))]
+            Self::Yaml => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Checks the name of an environment variable
+fn variable(name: &str) -> Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(invalid(format!("invalid variable name: {}", name)));
+    }
+
+    Ok(())
+}
+
+/// Expands `${NAME}` to the value of the environment variable `NAME`
+///
+/// Only the variables in `allowed` may be used. Since the environment is
+/// under the control of the caller, only the parent interface and the DNS
+/// servers of a subnet are expanded, and the values are restricted to
+/// characters which can't change the meaning of the value.
+fn interpolate(text: &str, allowed: &[String]) -> Result<String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "._-:/@%".contains(c);
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let end = rest
+            .find('}')
+            .ok_or_else(|| invalid("unterminated variable reference".into()))?;
+        let name = &rest[..end];
+        rest = &rest[end + 1..];
+
+        if !allowed.iter().any(|a| a == name) {
+            return Err(invalid(format!("variable {} is not allowed", name)));
+        }
+
+        let value =
+            std::env::var(name).map_err(|_| invalid(format!("variable {} is not set", name)))?;
+        if value.is_empty() || !value.chars().all(valid) {
+            return Err(invalid(format!("variable {} has an invalid value", name)));
+        }
+
+        debug!("interpolating variable: name={} value={}", name, value);
+        expanded.push_str(&value);
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Reads in a configuration file in the given format
+///
+/// References to environment variables (`${NAME}`) are expanded in the
+/// values which allow them (see `interpolate()`).
+pub fn read(format: Format, mut config: impl BufRead) -> Result<Config> {
+    let mut text = String::new();
+    config.read_to_string(&mut text)?;
+
+    let allowed = format.variables(&text)?;
+
+    match format {
+        Format::Line => load(text.as_bytes(), &allowed),
+        Format::Toml => load_toml(text.as_bytes(), &allowed),
+        Format::Yaml => load_yaml(text.as_bytes(), &allowed),
+    }
+}
+
+/// Reads in a structured (TOML) configuration
+fn load_toml(mut config: impl Read, allowed: &[String]) -> Result<Config> {
+    let mut text = String::new();
+    config.read_to_string(&mut text)?;
+
+    let document: Document = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+    document.config(allowed)
+}
+
+/// Reads in a structured (YAML) configuration
+#[cfg(feature = "yaml")]
+fn load_yaml(config: impl Read, allowed: &[String]) -> Result<Config> {
+    let document: Document = serde_yaml::from_reader(config).map_err(|e| invalid(e.to_string()))?;
+    document.config(allowed)
+}
+
+#[cfg(not(feature = "yaml"))]
+fn load_yaml(_: impl Read, _: &[String]) -> Result<Config> {
+    Err(Error::new(
+        ErrorKind::Other,
+        "ipvlan was built without YAML support",
+    ))
+}
+
+/// A whitespace-separated field of a line in the configuration
+struct Field<'a> {
+    line: usize,
+    column: usize,
+    text: &'a str,
+}
+
+impl Field<'_> {
+    /// Adds the location of the field to an error
+    fn locate<T>(&self, result: Result<T>) -> Result<T> {
+        result.context(|| format!("line {}, column {}", self.line, self.column))
+    }
+
+    /// Parses the field as a `what`
+    fn parse<T: FromStr>(&self, what: &str) -> Result<T>
+    where
+        T::Err: std::fmt::Display,
+    {
+        let result = self
+            .text
+            .parse()
+            .map_err(|e| invalid(format!("invalid {} '{}': {}", what, self.text, e)));
+        self.locate(result)
+    }
+
+    /// Expands the variables in the field (see `interpolate()`)
+    fn interpolate(&self, allowed: &[String]) -> Result<String> {
+        self.locate(interpolate(self.text, allowed))
+    }
+
+    /// Fails with an error at the location of the field
+    fn error<T>(&self, message: String) -> Result<T> {
+        self.locate(Err(invalid(message)))
+    }
+}
+
+/// Splits a line into fields, ignoring any comment
+fn fields(line: &str, number: usize) -> Vec<Field<'_>> {
+    let line = match line.find('#') {
+        Some(comment) => &line[..comment],
+        None => line,
+    };
+
+    let mut fields = Vec::new();
+    let mut start = None;
+    let end = std::iter::once((line.len(), ' '));
+    for (i, c) in line.char_indices().chain(end) {
+        match start {
+            None if !c.is_whitespace() => start = Some(i),
+            Some(s) if c.is_whitespace() => {
+                fields.push(Field {
+                    line: number,
+                    column: line[..s].chars().count() + 1,
+                    text: &line[s..i],
+                });
+                start = None;
+            }
+            _ => (),
+        }
+    }
+
+    fields
+}
+
+/// Reads in a line-based configuration (see the README for its directives)
+pub fn load(config: impl BufRead, allowed: &[String]) -> Result<Config> {
+    let mut parsed = Config::default();
+    let mut profile: Option<String> = None;
+
+    for (n, line) in config.lines().enumerate() {
+        let line = line?;
+        let fields = fields(&line, n + 1);
+        let (first, rest) = match &fields[..] {
+            [first, rest @ ..] => (first, rest),
+            [] => continue,
+        };
+
+        if first.text == "profile" {
+            let name = match rest {
+                [name] => name.text,
+                _ => return first.error("expected profile NAME".into()),
+            };
+
+            debug!("loading profile: name={}", name);
+            parsed.profiles.entry(name.into()).or_default();
+            profile = Some(name.into());
+            continue;
+        }
+
+        let target = match &profile {
+            Some(name) => parsed.profiles.get_mut(name).unwrap(),
+            None => &mut parsed,
+        };
+
+        entry(first, rest, target, allowed)?;
+    }
+
+    Ok(parsed)
+}
+
+/// Parses the fields of a line of the configuration into `config`
+fn entry(
+    first: &Field<'_>,
+    rest: &[Field<'_>],
+    config: &mut Config,
+    allowed: &[String],
+) -> Result<()> {
+    match first.text {
+        "loopback" => {
+            for field in rest {
+                debug!("loaded loopback address: address={}", field.text);
+                config.loopback.push(field.locate(address(field.text))?);
+            }
+            return Ok(());
+        }
+
+        "dummy" => {
+            for field in rest {
+                debug!("loaded dummy address: address={}", field.text);
+                config.dummy.push(field.locate(address(field.text))?);
+            }
+            return Ok(());
+        }
+
+        "sysctl" => {
+            for field in rest {
+                debug!("loaded sysctl: sysctl={}", field.text);
+                config.set(field.parse("sysctl")?);
+            }
+            return Ok(());
+        }
+
+        "nftables" => {
+            let path = match rest {
+                [path] => path,
+                _ => return first.error("expected nftables PATH".into()),
+            };
+
+            debug!("loaded nftables ruleset: path={}", path.text);
+            config.nftables = Some(path.locate(ruleset(path.text.into()))?);
+            return Ok(());
+        }
+
+        "route" => {
+            for field in rest {
+                debug!("loaded route: route={}", field.text);
+                config.routes.push(field.parse("route")?);
+            }
+            return Ok(());
+        }
+
+        "command" => {
+            if rest.is_empty() {
+                return first.error("expected command ARGV...".into());
+            }
+
+            debug!(
+                "loaded command: argv={:?}",
+                rest.iter().map(|f| f.text).collect::<Vec<_>>()
+            );
+            config.command = Some(rest.iter().map(|f| f.text.into()).collect());
+            return Ok(());
+        }
+
+        "lock-command" => {
+            if !rest.is_empty() {
+                return first.error("expected lock-command without arguments".into());
+            }
+
+            config.lock_command = true;
+            return Ok(());
+        }
+
+        "env" => {
+            for field in rest {
+                field.locate(variable(field.text))?;
+            }
+            return Ok(());
+        }
+
+        "no-scan" => {
+            if !rest.is_empty() {
+                return first.error("expected no-scan without arguments".into());
+            }
+
+            config.no_scan = true;
+            return Ok(());
+        }
+
+        "program" => {
+            for field in rest {
+                debug!("loaded program: program={}", field.text);
+                config.programs.push(field.parse("program")?);
+            }
+            return Ok(());
+        }
+
+        _ => (),
+    }
+
+    if let Ok(address) = first.text.parse::<IpAddr>() {
+        let owner = match rest {
+            [equals, owner] if equals.text == "=" => owner,
+            _ => return first.error("expected ADDRESS = user:NAME".into()),
+        };
+
+        debug!(
+            "loaded reservation: address={} owner={}",
+            address, owner.text
+        );
+        let reservation = owner.locate(Reservation::parse(address, owner.text))?;
+        config.reservations.push(reservation);
+        return Ok(());
+    }
+
+    let (subnet, range) = first.locate(self::subnet(first.text))?;
+
+    let mut settings = Settings {
+        range,
+        ..Default::default()
+    };
+    let mut rest = rest.iter();
+    while let Some(key) = rest.next() {
+        if key.text == "no-gateway" {
+            settings.no_gateway = true;
+            continue;
+        }
+
+        let value = match rest.next() {
+            Some(value) => value,
+            None => return key.error(format!("missing value for {}", key.text)),
+        };
+
+        match key.text {
+            "via" => settings.parent = Some(value.interpolate(allowed)?),
+            "vlan" => settings.vlan = Some(value.parse("vlan")?),
+            "dns" => {
+                let text = value.interpolate(allowed)?;
+                let value = Field {
+                    text: &text,
+                    ..*value
+                };
+                settings.dns.push(value.parse("dns server")?);
+            }
+            "search" => settings.search.push(value.text.into()),
+            "gw" => settings.gateway = Some(value.parse("gateway")?),
+            "mtu" => settings.mtu = Some(value.parse("mtu")?),
+            "mac" => settings.mac = Some(value.parse("mac policy")?),
+            "count" => settings.count = Some(value.parse("count")?),
+            "exclude" => settings.exclude.push(value.parse("range")?),
+            "lease" => settings.lease = Some(Duration::from_secs(value.parse("lease")?)),
+            "grace" => settings.grace = Some(Duration::from_secs(value.parse("grace")?)),
+            "strategy" => settings.strategy = Some(value.parse("strategy")?),
+            "metric" => settings.metric = Some(value.parse("metric")?),
+            "lifetime" => settings.lifetime = Some(Duration::from_secs(value.parse("lifetime")?)),
+            "disable" => settings.disable.push(value.parse("offload")?),
+            "rate" => settings.rate = Some(value.parse("rate")?),
+
+            "allow" => {
+                let name = match rest.next() {
+                    Some(name) => name,
+                    None => return value.error(format!("missing name for allow {}", value.text)),
+                };
+
+                let principal = format!("{}:{}", value.text, name.text);
+                settings
+                    .allow
+                    .push(value.locate(principal.parse().map_err(invalid))?);
+            }
+
+            _ => return key.error(format!("unknown option: {}", key.text)),
+        }
+    }
+
+    first.locate(settings.validate(&subnet))?;
+    debug!("loaded subnet: subnet={} settings={:?}", subnet, settings);
+    config.subnets.insert(subnet, settings);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::ip;
+
+    fn subnet(s: &str) -> Subnet {
+        super::subnet(s).unwrap().0
+    }
+
+    #[test]
+    fn fields() {
+        let fields = super::fields("  10.0.0.0/24  via eth0 # comment", 3);
+        let texts: Vec<&str> = fields.iter().map(|f| f.text).collect();
+        assert_eq!(texts, ["10.0.0.0/24", "via", "eth0"]);
+        assert!(fields.iter().all(|f| f.line == 3));
+        assert_eq!(fields[0].column, 3);
+        assert_eq!(fields[1].column, 16);
+        assert_eq!(fields[2].column, 20);
+
+        assert!(super::fields("", 1).is_empty());
+        assert!(super::fields("   # only a comment", 1).is_empty());
+    }
+
+    #[test]
+    fn load_line() {
+        let text = "\
+            10.2.0.0/24 via eth1 dns 10.2.0.53 count 2 gw 10.2.0.1\n\
+            loopback 10.255.0.1/32\n\
+            profile staging\n\
+            10.3.0.0/24 via eth2\n";
+        let config = load(text.as_bytes(), &[]).unwrap();
+
+        let settings = &config.subnets[&subnet("10.2.0.0/24")];
+        assert_eq!(settings.parent.as_deref(), Some("eth1"));
+        assert_eq!(settings.dns, [ip("10.2.0.53")]);
+        assert_eq!(settings.count, Some(2));
+        assert_eq!(settings.gateway, Some(ip("10.2.0.1")));
+        assert_eq!(config.loopback, [(ip("10.255.0.1"), 32)]);
+
+        let staging = &config.profiles["staging"];
+        assert!(staging.subnets.contains_key(&subnet("10.3.0.0/24")));
+        assert!(!config.subnets.contains_key(&subnet("10.3.0.0/24")));
+    }
+
+    #[test]
+    fn load_line_errors() {
+        let error = load("10.2.0.0/24 colour blue".as_bytes(), &[]).unwrap_err();
+        assert!(error.to_string().contains("line 1, column 13"), "{}", error);
+
+        assert!(load("10.2.0.0/24 count".as_bytes(), &[]).is_err());
+        assert!(load("10.2.0.0/40".as_bytes(), &[]).is_err());
+        assert!(load("10.2.0.0/24 count 300".as_bytes(), &[]).is_err());
+        assert!(load("profile".as_bytes(), &[]).is_err());
+    }
+
+    #[test]
+    fn load_range() {
+        let config = load("10.2.0.10-10.2.0.20/24".as_bytes(), &[]).unwrap();
+        let settings = &config.subnets[&subnet("10.2.0.0/24")];
+        assert_eq!(settings.range, Some("10.2.0.10-10.2.0.20".parse().unwrap()));
+
+        assert!(load("10.2.0.20-10.2.0.10/24".as_bytes(), &[]).is_err());
+        assert!(load("10.2.0.10-10.2.0.20".as_bytes(), &[]).is_err());
+        assert!(load("10.2.0.10-10.2.0.20/40".as_bytes(), &[]).is_err());
+        assert!(load("10.2.0.10-10.2.1.20/24".as_bytes(), &[]).is_err());
+    }
+
+    #[test]
+    fn fixed_mac() {
+        let fixed = "mac 02:00:5e:10:00:01";
+        assert!(load(format!("10.2.0.0/24 {}", fixed).as_bytes(), &[]).is_err());
+        assert!(load(format!("10.2.0.7/32 {}", fixed).as_bytes(), &[]).is_ok());
+        assert!(load(format!("10.2.0.7-10.2.0.7/24 {}", fixed).as_bytes(), &[]).is_ok());
+        assert!(load(format!("10.2.0.7/32 count 2 {}", fixed).as_bytes(), &[]).is_err());
+        assert!(load("10.2.0.0/24 mac stable".as_bytes(), &[]).is_ok());
+    }
+
+    #[test]
+    fn interpolate() {
+        std::env::set_var("IPVLAN_TEST_PARENT", "eth7");
+        std::env::set_var("IPVLAN_TEST_BAD", "eth7 dns 192.0.2.1");
+        let allowed = vec!["IPVLAN_TEST_PARENT".to_string(), "IPVLAN_TEST_BAD".into()];
+
+        let expanded = super::interpolate("${IPVLAN_TEST_PARENT}.30", &allowed).unwrap();
+        assert_eq!(expanded, "eth7.30");
+        assert_eq!(super::interpolate("eth0", &allowed).unwrap(), "eth0");
+
+        assert!(super::interpolate("${IPVLAN_TEST_BAD}", &allowed).is_err());
+        assert!(super::interpolate("${IPVLAN_TEST_UNSET}", &allowed).is_err());
+        assert!(super::interpolate("${HOME}", &allowed).is_err());
+        assert!(super::interpolate("${IPVLAN_TEST_PARENT", &allowed).is_err());
+    }
+
+    #[test]
+    fn read_line() {
+        std::env::set_var("IPVLAN_TEST_DNS", "10.2.0.53");
+        let text = "\
+            env IPVLAN_TEST_DNS\n\
+            10.2.0.0/24 dns ${IPVLAN_TEST_DNS}\n\
+            command /bin/echo ${IPVLAN_TEST_DNS}\n";
+        let config = read(Format::Line, text.as_bytes()).unwrap();
+
+        let settings = &config.subnets[&subnet("10.2.0.0/24")];
+        assert_eq!(settings.dns, [ip("10.2.0.53")]);
+
+        // Only the values which allow variables are expanded.
+        let command = config.command.unwrap();
+        assert_eq!(command, ["/bin/echo", "${IPVLAN_TEST_DNS}"]);
+    }
+
+    #[test]
+    fn read_toml() {
+        std::env::set_var("IPVLAN_TEST_TOML_DNS", "10.3.0.53");
+        let text = r#"
+            env = ["IPVLAN_TEST_TOML_DNS"]
+
+            [subnets."10.2.0.100-10.2.0.199/24"]
+            parent = "eth1"
+            count = 2
+
+            [profiles.staging.subnets."10.3.0.0/24"]
+            gateway = "10.3.0.1"
+            dns = ["${IPVLAN_TEST_TOML_DNS}"]
+        "#;
+        let config = read(Format::Toml, text.as_bytes()).unwrap();
+
+        let settings = &config.subnets[&subnet("10.2.0.0/24")];
+        assert_eq!(settings.parent.as_deref(), Some("eth1"));
+        assert_eq!(settings.count, Some(2));
+        assert_eq!(
+            settings.range,
+            Some("10.2.0.100-10.2.0.199".parse().unwrap())
+        );
+
+        let staging = &config.profiles["staging"].subnets[&subnet("10.3.0.0/24")];
+        assert_eq!(staging.gateway, Some(ip("10.3.0.1")));
+        assert_eq!(staging.dns, [ip("10.3.0.53")]);
+
+        let unknown = "[subnets.\"10.2.0.0/24\"]\ncolour = \"blue\"\n";
+        assert!(read(Format::Toml, unknown.as_bytes()).is_err());
+    }
+}
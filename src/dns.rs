@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-namespace resolver configuration
+
+use std::ffi::CString;
+use std::fs::{remove_file, OpenOptions};
+use std::io::{Error, Result, Write};
+use std::net::IpAddr;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use caps::Capability;
+use log::info;
+
+/// The resolver configuration file
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Renders a resolver configuration file
+fn render(servers: &[IpAddr], search: &[String]) -> String {
+    let mut conf = String::from("# Generated by ipvlan\n");
+
+    for server in servers {
+        conf += &format!("nameserver {}\n", server);
+    }
+
+    if !search.is_empty() {
+        conf += &format!("search {}\n", search.join(" "));
+    }
+
+    conf
+}
+
+/// Overrides the resolver configuration in a new mount namespace
+///
+/// The generated file is written to the state directory, bind mounted over
+/// `/etc/resolv.conf` and unlinked again; the mount keeps it alive.
+pub fn configure(servers: &[IpAddr], search: &[String]) -> Result<()> {
+    info!("configuring dns: servers={:?} search={:?}", servers, search);
+    crate::unshare(libc::CLONE_NEWNS)?;
+
+    // Keep our mounts from propagating back to the host.
+    let root = CString::new("/").unwrap();
+    caps::with(Capability::CAP_SYS_ADMIN, || {
+        match unsafe {
+            libc::mount(
+                std::ptr::null(),
+                root.as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            )
+        } {
+            -1 => Err(Error::last_os_error()),
+            0 => Ok(()),
+            _ => unreachable!(),
+        }
+    })?;
+
+    let path =
+        Path::new(crate::state::STATE_DIR).join(format!("resolv-{}.conf", std::process::id()));
+    let file = caps::with(Capability::CAP_DAC_OVERRIDE, || -> Result<_> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o644)
+            .open(&path)?;
+        file.write_all(render(servers, search).as_bytes())?;
+        Ok(file)
+    })?;
+
+    let result = crate::netns::bind(&file, Path::new(RESOLV_CONF));
+    caps::with(Capability::CAP_DAC_OVERRIDE, || remove_file(&path))?;
+    result
+}
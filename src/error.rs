@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Errors reported to the user
+//!
+//! These convert into `std::io::Error` (keeping an appropriate kind), so they
+//! can be returned wherever an `std::io::Result` is expected.
+
+use crate::netlink::Subnet;
+
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use caps::Capability;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A required capability isn't permitted
+    MissingCapability(Capability),
+
+    /// A capability is permitted or effective that shouldn't be
+    ExcessCapability(Capability),
+
+    /// No interface has an address within the subnet
+    NoGateway(Subnet),
+
+    /// A configuration or state file (or directory) may have been tampered
+    /// with
+    Insecure(PathBuf, &'static str),
+
+    /// An operation failed
+    Context(String, std::io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingCapability(cap) => write!(f, "{} is not permitted", cap),
+            Self::ExcessCapability(cap) => write!(f, "{} must not be permitted", cap),
+            Self::NoGateway(subnet) => write!(f, "no interface has an address within {}", subnet),
+            Self::Insecure(path, why) => write!(f, "{}: {}", path.display(), why),
+            Self::Context(context, error) => write!(f, "{}: {}", context, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Context(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(value: Error) -> Self {
+        let kind = match &value {
+            Error::MissingCapability(..) => ErrorKind::PermissionDenied,
+            Error::ExcessCapability(..) => ErrorKind::PermissionDenied,
+            Error::NoGateway(..) => ErrorKind::NotFound,
+            Error::Insecure(..) => ErrorKind::PermissionDenied,
+            Error::Context(_, error) => error.kind(),
+        };
+
+        Self::new(kind, value)
+    }
+}
+
+/// Adds a description of the failed operation to an error
+pub trait Context<T> {
+    fn context(self, context: impl FnOnce() -> String) -> std::io::Result<T>;
+}
+
+impl<T, E: Into<std::io::Error>> Context<T> for Result<T, E> {
+    fn context(self, context: impl FnOnce() -> String) -> std::io::Result<T> {
+        self.map_err(|e| Error::Context(context(), e.into()).into())
+    }
+}
@@ -0,0 +1,223 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Leases on the addresses released by namespaces
+//!
+//! When a namespace's record is removed, its addresses are remembered along
+//! with the user who held them. The subnet's `lease` and `grace` settings then
+//! decide when the addresses may be assigned again.
+
+use crate::state::{self, STATE_DIR};
+
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::net::IpAddr;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use caps::Capability;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+/// An address released by a namespace
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Lease {
+    pub address: IpAddr,
+
+    /// The uid of the user who held the address
+    pub uid: u32,
+
+    /// When the address was released (in seconds since the epoch)
+    pub released: u64,
+}
+
+impl Lease {
+    /// How long ago the address was released
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.released))
+    }
+
+    /// Whether the address is still kept from `uid`
+    ///
+    /// During the `grace` period it is kept from everyone, and until the
+    /// `lease` expires from everyone but the user who held it.
+    pub fn held(&self, uid: u32, grace: Duration, lease: Duration) -> bool {
+        let age = self.age();
+        age < grace || (age < lease && uid != self.uid)
+    }
+
+    /// Whether the address is free for everyone again
+    pub fn expired(&self, grace: Duration, lease: Duration) -> bool {
+        self.age() >= grace.max(lease)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn path() -> PathBuf {
+    [STATE_DIR, "leases"].iter().collect()
+}
+
+/// Locks the lease database
+///
+/// The database is replaced (not rewritten) on every change, so the lock
+/// lives in a file of its own.
+fn lock() -> Result<File> {
+    state::directory(Path::new(STATE_DIR))?;
+
+    let path: PathBuf = [STATE_DIR, "leases.lock"].iter().collect();
+    let file = caps::with(Capability::CAP_DAC_OVERRIDE, || {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .mode(0o0644)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&path)
+    })?;
+    if file.metadata()?.uid() != 0 {
+        state::chown(&file)?;
+    }
+
+    crate::lock(&file, None)?;
+    Ok(file)
+}
+
+fn read() -> Result<Vec<Lease>> {
+    let file = match state::open(&path()) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        file => file?,
+    };
+
+    serde_json::from_reader(file).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+fn write(leases: &[Lease]) -> Result<()> {
+    state::replace(&path(), |file| {
+        serde_json::to_writer_pretty(file, leases)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    })
+}
+
+/// Loads all leases
+pub fn list() -> Result<Vec<Lease>> {
+    let _lock = lock()?;
+    read()
+}
+
+/// Loads all leases without locking (or creating) the database
+///
+/// Dry runs use this to leave the state directory untouched. A concurrent
+/// change may be missed, which is harmless as nothing is assigned.
+pub fn peek() -> Result<Vec<Lease>> {
+    read()
+}
+
+/// Records that `uid` released `addresses`
+pub fn release(uid: u32, addresses: &[IpAddr]) -> Result<()> {
+    if addresses.is_empty() {
+        return Ok(());
+    }
+
+    let _lock = lock()?;
+    let mut leases = read()?;
+    leases.retain(|l| !addresses.contains(&l.address));
+
+    let released = now();
+    for address in addresses {
+        debug!("releasing address: address={} uid={}", address, uid);
+        leases.push(Lease {
+            address: *address,
+            uid,
+            released,
+        });
+    }
+
+    write(&leases)
+}
+
+/// Forgets the leases which have `expired`
+pub fn prune(expired: impl Fn(&Lease) -> bool) -> Result<()> {
+    let _lock = lock()?;
+    let mut leases = read()?;
+    let count = leases.len();
+    leases.retain(|l| !expired(l));
+
+    if leases.len() != count {
+        debug!("pruned leases: count={}", count - leases.len());
+        write(&leases)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::ip;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn lease(ago: u64) -> Lease {
+        Lease {
+            address: ip("10.0.0.10"),
+            uid: 1000,
+            released: now() - ago,
+        }
+    }
+
+    #[test]
+    fn age() {
+        assert!(lease(90).age() >= Duration::from_secs(90));
+        assert!(lease(90).age() < Duration::from_secs(100));
+
+        let future = Lease {
+            released: now() + 3600,
+            ..lease(0)
+        };
+        assert_eq!(future.age(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn grace() {
+        let lease = lease(30);
+        assert!(lease.held(1000, MINUTE, HOUR));
+        assert!(lease.held(1001, MINUTE, HOUR));
+        assert!(!lease.expired(MINUTE, HOUR));
+    }
+
+    #[test]
+    fn leased() {
+        let lease = lease(600);
+        assert!(!lease.held(1000, MINUTE, HOUR));
+        assert!(lease.held(1001, MINUTE, HOUR));
+        assert!(!lease.expired(MINUTE, HOUR));
+    }
+
+    #[test]
+    fn expired() {
+        let lease = lease(7200);
+        assert!(!lease.held(1000, MINUTE, HOUR));
+        assert!(!lease.held(1001, MINUTE, HOUR));
+        assert!(lease.expired(MINUTE, HOUR));
+
+        let unset = Duration::default();
+        assert!(lease.expired(unset, unset));
+        assert!(!lease.held(1001, unset, unset));
+    }
+
+    #[test]
+    fn serde() {
+        let json = r#"{"address":"10.0.0.10","uid":1000,"released":1600000000}"#;
+        let lease: Lease = serde_json::from_str(json).unwrap();
+        assert_eq!(lease.address, ip("10.0.0.10"));
+        assert_eq!(lease.uid, 1000);
+        assert_eq!(lease.released, 1_600_000_000);
+        assert_eq!(serde_json::to_string(&lease).unwrap(), json);
+    }
+}
@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Choosing the hardware addresses of macvlan interfaces
+
+use crate::netlink::{Interface, Subnet};
+
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+/// Formats a hardware address as `xx:xx:xx:xx:xx:xx`
+pub fn format(mac: &[u8]) -> String {
+    let octets: Vec<String> = mac.iter().map(|o| format!("{:02x}", o)).collect();
+    octets.join(":")
+}
+
+/// How to choose the hardware address of an interface
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// A random locally administered address (chosen by the kernel)
+    Random,
+
+    /// A locally administered address derived from the parent's address,
+    /// the subnet and the user
+    Stable,
+
+    /// The given address
+    Fixed([u8; 6]),
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Random => write!(f, "random"),
+            Self::Stable => write!(f, "stable"),
+            Self::Fixed(mac) => write!(f, "{}", format(mac)),
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid mac policy: {}", s);
+
+        match s {
+            "random" => Ok(Self::Random),
+            "stable" => Ok(Self::Stable),
+            _ => {
+                let mut mac = [0u8; 6];
+                let mut octets = s.split(':');
+                for octet in mac.iter_mut() {
+                    let field = octets.next().ok_or_else(invalid)?;
+                    if field.len() != 2 {
+                        return Err(invalid());
+                    }
+
+                    *octet = u8::from_str_radix(field, 16).map_err(|_| invalid())?;
+                }
+
+                // Multicast addresses can't be assigned to an interface.
+                if octets.next().is_some() || mac[0] & 0x01 != 0 {
+                    return Err(invalid());
+                }
+
+                Ok(Self::Fixed(mac))
+            }
+        }
+    }
+}
+
+impl Policy {
+    /// Chooses the address of an interface on `parent` carrying `subnet` for
+    /// `uid`, or `None` to leave the choice to the kernel
+    pub fn address(&self, parent: &Interface, subnet: &Subnet, uid: u32) -> Option<[u8; 6]> {
+        match self {
+            Self::Random => None,
+            Self::Fixed(mac) => Some(*mac),
+            Self::Stable => {
+                let mut hasher = Sha256::new();
+                hasher.update(parent.mac());
+                hasher.update(subnet.to_string());
+                hasher.update(uid.to_be_bytes());
+
+                let mut mac = [0u8; 6];
+                mac.copy_from_slice(&hasher.finalize()[..6]);
+                mac[0] = (mac[0] | 0x02) & !0x01;
+                Some(mac)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("random".parse::<Policy>(), Ok(Policy::Random));
+        assert_eq!("stable".parse::<Policy>(), Ok(Policy::Stable));
+        assert_eq!(
+            "02:00:5e:10:00:01".parse::<Policy>(),
+            Ok(Policy::Fixed([0x02, 0x00, 0x5e, 0x10, 0x00, 0x01]))
+        );
+    }
+
+    #[test]
+    fn from_str_invalid() {
+        // Multicast
+        assert!("01:00:5e:10:00:01".parse::<Policy>().is_err());
+
+        assert!("02:00:5e:10:00".parse::<Policy>().is_err());
+        assert!("02:00:5e:10:00:01:02".parse::<Policy>().is_err());
+        assert!("2:00:5e:10:00:01".parse::<Policy>().is_err());
+        assert!("02:00:5e:10:00:zz".parse::<Policy>().is_err());
+        assert!("fixed".parse::<Policy>().is_err());
+    }
+
+    #[test]
+    fn display() {
+        for policy in &["random", "stable", "02:00:5e:10:00:01"] {
+            assert_eq!(policy.parse::<Policy>().unwrap().to_string(), *policy);
+        }
+    }
+}
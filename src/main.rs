@@ -2,21 +2,46 @@
 
 #![deny(clippy::all)]
 
+mod allocate;
+mod config;
+mod dns;
+mod error;
+mod lease;
+mod mac;
 mod netlink;
+mod netns;
+mod nft;
+mod program;
+mod publish;
+mod random;
+mod range;
+mod state;
+mod status;
+mod sysctl;
+#[cfg(test)]
+mod testing;
+mod user;
 
-use netlink::{Address, Interface, Subnet};
+use error::Context;
+use netlink::{
+    Address, AddressOptions, Connection, Interface, IpvlanMode, MacvlanMode, Offload, Qdisc, Route,
+    RouteKind, Subnet,
+};
 
 use std::collections::{HashMap, HashSet};
 use std::fs::{read_dir, read_link, File};
-use std::io::{BufRead, BufReader, Result};
+use std::io::{BufReader, Error, ErrorKind, Result};
 use std::net::IpAddr;
 use std::os::unix::prelude::*;
 use std::os::unix::process::CommandExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use caps::{CapSet, Capability};
+use log::{debug, info, warn, LevelFilter};
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
 fn flock(fd: &impl AsRawFd, flags: libc::c_int) -> Result<()> {
@@ -27,6 +52,34 @@ fn flock(fd: &impl AsRawFd, flags: libc::c_int) -> Result<()> {
     }
 }
 
+/// Exclusively locks `fd`, giving up after `timeout` (if any)
+fn lock(fd: &impl AsRawFd, timeout: Option<Duration>) -> Result<()> {
+    let deadline = match timeout {
+        None => return flock(fd, libc::LOCK_EX),
+        Some(timeout) => Instant::now() + timeout,
+    };
+
+    let mut delay = Duration::from_millis(10);
+    loop {
+        match flock(fd, libc::LOCK_EX | libc::LOCK_NB) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            result => return result,
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "another ipvlan invocation holds the lock",
+            ));
+        }
+
+        debug!("waiting for lock: delay={:?}", delay);
+        std::thread::sleep(delay.min(deadline - now));
+        delay = (delay * 2).min(Duration::from_secs(1));
+    }
+}
+
 fn setns(fd: &impl AsRawFd, flags: libc::c_int) -> Result<()> {
     caps::with(Capability::CAP_SYS_ADMIN, || {
         match unsafe { libc::setns(fd.as_raw_fd(), flags) } {
@@ -47,6 +100,16 @@ fn unshare(flags: libc::c_int) -> Result<()> {
     })
 }
 
+fn sethostname(name: &str) -> Result<()> {
+    caps::with(Capability::CAP_SYS_ADMIN, || {
+        match unsafe { libc::sethostname(name.as_ptr() as *const libc::c_char, name.len()) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            0 => Ok(()),
+            _ => unreachable!(),
+        }
+    })
+}
+
 /// Returns an iterator to all `/proc/<pid>` directories
 fn processes() -> Result<impl Iterator<Item = PathBuf>> {
     Ok(read_dir("/proc")?.filter_map(Result::ok).filter_map(|e| {
@@ -57,8 +120,8 @@ fn processes() -> Result<impl Iterator<Item = PathBuf>> {
     }))
 }
 
-/// Loads all unique network namespaces for all processes
-fn load_namespaces() -> Result<Vec<File>> {
+/// Loads all unique network namespaces in use by processes
+fn process_namespaces() -> Result<HashMap<(u64, u64), File>> {
     let mut namespaces = HashMap::new();
 
     for process in processes()? {
@@ -81,176 +144,2255 @@ fn load_namespaces() -> Result<Vec<File>> {
         }
     }
 
+    Ok(namespaces)
+}
+
+/// Loads all unique network namespaces for all processes and mounts
+fn load_namespaces() -> Result<Vec<File>> {
+    let mut namespaces = process_namespaces()?;
+
+    for path in netns::list()? {
+        if let Ok(file) = File::open(path) {
+            if let Ok(metadata) = file.metadata() {
+                namespaces.insert((metadata.dev(), metadata.ino()), file);
+            }
+        }
+    }
+
     Ok(namespaces.into_iter().map(|(_, v)| v).collect())
 }
 
 /// Finds all in-use ip addresses for each subnet in each namespace
-fn scan_namespaces(subnets: HashSet<Subnet>) -> Result<HashSet<IpAddr>> {
-    let saved = File::open("/proc/self/ns/net")?;
+fn scan_namespaces(subnets: &HashSet<Subnet>) -> Result<HashSet<IpAddr>> {
     let mut used = HashSet::<IpAddr>::new();
 
+    // Only dump the addresses of the family in use, if there is just one.
+    let mut filter = netlink::AddressFilter::default();
+    if subnets.iter().all(|s| s.address().is_ipv4()) {
+        filter.family = Some(libc::AF_INET as u16);
+    } else if subnets.iter().all(|s| s.address().is_ipv6()) {
+        filter.family = Some(libc::AF_INET6 as u16);
+    }
+
     let namespaces = caps::with(Capability::CAP_DAC_OVERRIDE, load_namespaces)?;
-    caps::drop(None, CapSet::Permitted, Capability::CAP_DAC_OVERRIDE)?;
+    info!("scanning {} network namespaces", namespaces.len());
     for ns in namespaces {
-        setns(&ns, libc::CLONE_NEWNET)?;
+        let mut nl = caps::with(Capability::CAP_SYS_ADMIN, || Connection::in_namespace(&ns))?;
 
-        for address in Address::list()? {
-            for subnet in &subnets {
+        for address in Address::list_filtered(&mut nl, filter)? {
+            for subnet in subnets {
                 let addr = address.address();
                 if subnet.contains(addr) {
+                    debug!("address in use: address={} subnet={}", addr, subnet);
                     used.insert(addr);
                 }
             }
         }
     }
 
-    setns(&saved, libc::CLONE_NEWNET)?;
     Ok(used)
 }
 
-/// Reads in the configuration, deduplicating subnets
-fn load_config(config: impl BufRead) -> Result<HashSet<Subnet>> {
-    let mut subnets = HashSet::<Subnet>::new();
+/// Finds the in-use ip addresses for each subnet in the records of the
+/// namespaces created by ipvlan (and in the current namespace)
+///
+/// This is much cheaper than `scan_namespaces()`, but misses addresses
+/// assigned by anything else. The records of namespaces which no longer
+/// exist are removed, leasing their addresses (see `lease`), so that they
+/// are kept from others like those released on exit (except in dry runs).
+fn recorded_addresses(subnets: &HashSet<Subnet>, dry_run: bool) -> Result<HashSet<IpAddr>> {
+    let mut addresses: Vec<IpAddr> = Address::list()?.iter().map(|a| a.address()).collect();
+    for record in state::Record::list()? {
+        if !record.alive() {
+            if !dry_run {
+                info!("removing stale record: pid={}", record.pid);
+                record.remove()?;
+            }
+            continue;
+        }
+
+        let links = record.links.iter();
+        addresses.extend(links.flat_map(|l| l.addresses.iter().map(|a| a.address)));
+    }
+
+    let mut used = HashSet::new();
+    for addr in addresses {
+        if subnets.iter().any(|s| s.contains(addr)) {
+            debug!("address in use: address={}", addr);
+            used.insert(addr);
+        }
+    }
+
+    Ok(used)
+}
+
+/// An address requested on the command line
+#[derive(Debug)]
+struct Request {
+    address: IpAddr,
+    prefix: Option<u8>,
+}
+
+impl std::fmt::Display for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.prefix {
+            Some(prefix) => write!(f, "{}/{}", self.address, prefix),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut split = s.splitn(2, '/');
+        let address = split.next().unwrap_or_default();
+
+        Ok(Self {
+            address: address.parse::<IpAddr>().map_err(|e| format!("{}", e))?,
+            prefix: match split.next() {
+                Some(prefix) => Some(prefix.parse::<u8>().map_err(|e| format!("{}", e))?),
+                None => None,
+            },
+        })
+    }
+}
+
+impl Request {
+    /// Whether the requested address belongs to the subnet
+    fn within(&self, subnet: &Subnet) -> bool {
+        subnet.contains(self.address) && self.prefix.iter().all(|p| *p == subnet.prefix())
+    }
+}
+
+/// The kind of interface to create on each parent
+#[derive(Copy, Clone, Debug)]
+enum Driver {
+    Ipvlan,
+    Macvlan,
+    Veth,
+}
+
+impl std::fmt::Display for Driver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ipvlan => write!(f, "ipvlan"),
+            Self::Macvlan => write!(f, "macvlan"),
+            Self::Veth => write!(f, "veth"),
+        }
+    }
+}
+
+impl FromStr for Driver {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ipvlan" => Ok(Self::Ipvlan),
+            "macvlan" => Ok(Self::Macvlan),
+            "veth" => Ok(Self::Veth),
+            _ => Err(format!("invalid driver: {}", s)),
+        }
+    }
+}
+
+/// An ipvlan interface to create in the new namespace
+struct Link {
+    name: String,
+    parent: Interface,
+    mode: IpvlanMode,
+    mtu: u32,
+    mac: Option<[u8; 6]>,
+    addresses: Vec<(IpAddr, Address)>,
+    defaults: Vec<Route>,
+    routes: Vec<Route>,
+    disable: Vec<Offload>,
+    rate: Option<u64>,
+}
+
+/// Expands an interface name template for a parent and its gateways
+fn ifname(template: &str, n: usize, parent: &Interface, gateways: &[Address]) -> Result<String> {
+    let subnet = gateways
+        .first()
+        .map(|g| g.subnet().address().to_string())
+        .unwrap_or_default()
+        .replace(&['.', ':'][..], "-");
+
+    let name = template
+        .replace("{n}", &n.to_string())
+        .replace("{parent}", &parent.to_string())
+        .replace("{subnet}", &subnet);
+
+    // The kernel limits names to IFNAMSIZ (16) bytes including the NUL.
+    if name.is_empty() || name.len() > 15 || name.contains(|c: char| c == '/' || c.is_whitespace())
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid interface name: {}", name),
+        ));
+    }
+
+    Ok(name)
+}
+
+/// Prints the planned namespace setup without touching the system
+fn print_plan(
+    links: &[Link],
+    config: &config::Config,
+    dns: &[IpAddr],
+    search: &[String],
+    hostname: Option<&str>,
+    options: &Options,
+) {
+    if let Some(hostname) = hostname {
+        println!("hostname {}", hostname);
+    }
+
+    for server in dns {
+        println!("nameserver {}", server);
+    }
+
+    if !search.is_empty() {
+        println!("search {}", search.join(" "));
+    }
+
+    for link in links {
+        match options.driver {
+            Driver::Ipvlan => {
+                println!("{} (ipvlan {} on {})", link.name, link.mode, link.parent)
+            }
+            Driver::Macvlan => println!(
+                "{} (macvlan {} on {})",
+                link.name, options.macvlan_mode, link.parent
+            ),
+            Driver::Veth => println!(
+                "{} (veth on {})",
+                link.name,
+                options
+                    .bridge
+                    .as_deref()
+                    .unwrap_or(&link.parent.to_string())
+            ),
+        }
+
+        println!("    mtu {}", link.mtu);
+        if let Some(mac) = &link.mac {
+            println!("    mac {}", mac::format(mac));
+        }
+        for (address, gateway) in &link.addresses {
+            let subnet = gateway.subnet();
+            println!("    address {}/{}", address, subnet.prefix());
+        }
+
+        for route in &link.defaults {
+            match (route.gateway(), route.metric()) {
+                (Some(gateway), Some(metric)) => {
+                    println!("    route default via {} metric {}", gateway, metric)
+                }
+                (Some(gateway), None) => println!("    route default via {}", gateway),
+                (None, _) => unreachable!(),
+            }
+        }
+
+        for route in &link.routes {
+            match (route.kind(), route.gateway()) {
+                (RouteKind::Unicast, Some(gateway)) => {
+                    println!("    route {} via {}", route.destination(), gateway)
+                }
+                (RouteKind::Unicast, None) => println!("    route {}", route.destination()),
+                (kind, _) => println!("    route {} {}", kind, route.destination()),
+            }
+        }
+    }
+
+    for publish in &options.publish {
+        println!("publish {}", publish);
+    }
+
+    for sysctl in &config.sysctl {
+        println!("sysctl {}", sysctl);
+    }
+
+    if let Some(ruleset) = &config.nftables {
+        println!("nftables {}", ruleset);
+    }
+
+    if !config.dummy.is_empty() {
+        println!("{} (dummy)", DUMMY);
+        for (address, prefix) in &config.dummy {
+            println!("    address {}/{}", address, prefix);
+        }
+    }
+
+    if options.no_loopback {
+        return;
+    }
+
+    println!("lo");
+    println!("    address ::1/128");
+    println!("    address 127.0.0.1/8");
+    for (address, prefix) in &config.loopback {
+        println!("    address {}/{}", address, prefix);
+    }
+}
+
+/// Prints the addresses assigned to `links`
+fn print_addrs(links: &[state::Link], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(links)?);
+        return Ok(());
+    }
 
-    for line in config.lines() {
-        let line = line?;
-        if !line.starts_with('#') {
-            subnets.insert(line.parse()?);
+    for link in links {
+        for a in &link.addresses {
+            println!("{} {}/{} via {}", link.name, a.address, a.prefix, a.gateway);
         }
     }
 
-    Ok(subnets)
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+enum Action {
+    /// Joins a persistent namespace and executes a command.
+    Attach {
+        /// The name of the namespace (see --name).
+        name: String,
+
+        /// The binary to execute and its arguments
+        #[structopt(default_value = "/bin/bash")]
+        argv: Vec<String>,
+    },
+
+    /// Joins a namespace, by name or pid, and executes a command.
+    Exec {
+        /// The name of the namespace (see --name) or the pid holding it.
+        target: String,
+
+        /// The binary to execute and its arguments
+        #[structopt(default_value = "/bin/bash")]
+        argv: Vec<String>,
+    },
+
+    /// Lists the namespaces created by ipvlan.
+    List {
+        /// Print the namespaces as JSON.
+        #[structopt(long)]
+        json: bool,
+
+        /// Only list namespaces with this label, as KEY=VALUE (may be repeated).
+        #[structopt(long = "label", number_of_values = 1, parse(try_from_str = parse_pair))]
+        labels: Vec<(String, String)>,
+    },
+
+    /// Reports on the namespace of the current process.
+    Status {
+        /// Print the status as JSON.
+        #[structopt(long)]
+        json: bool,
+    },
+
+    /// Sets up a namespace and holds it open until terminated (SIGHUP
+    /// reloads the allowed programs and the locked command instead).
+    Daemon,
+
+    /// Removes the records and namespaces left behind by ipvlan.
+    Cleanup {
+        /// Also remove persistent namespaces that no process is using.
+        #[structopt(long)]
+        unused: bool,
+    },
+
+    /// Checks the configuration files and the subnets they define.
+    Validate,
+
+    /// Prints shell completions to stdout.
+    Completions {
+        /// The shell to generate completions for.
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
 }
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "ipvlan", about = "Builds an ipvlan network namespace.")]
 struct Options {
-    /// The ipvlan subnet configuration file.
-    #[structopt(short, long, default_value = "/etc/ipvlan.conf")]
-    config: PathBuf,
+    /// The ipvlan subnet configuration file or directory, or - for stdin
+    /// (may be repeated; later files take precedence). Defaults to /etc/ipvlan.conf
+    /// followed by the files in /etc/ipvlan.conf.d.
+    #[structopt(short, long, number_of_values = 1)]
+    config: Vec<PathBuf>,
+
+    /// The format of the configuration files (line, toml or yaml; default:
+    /// chosen by extension).
+    #[structopt(long)]
+    config_format: Option<config::Format>,
+
+    /// The configuration profile to use, in addition to the settings outside
+    /// of any profile.
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// A subnet to use in addition to (or, without -c, instead of) the
+    /// configuration files (may be repeated; root only).
+    #[structopt(short, long = "subnet", number_of_values = 1)]
+    subnets: Vec<Subnet>,
+
+    /// Increase the logging verbosity (may be repeated).
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+
+    /// The parent interface for a subnet, as INTERFACE=SUBNET (may be repeated).
+    #[structopt(long = "parent", number_of_values = 1, parse(try_from_str = parse_parent))]
+    parents: Vec<(String, Subnet)>,
 
-    /// The binary to execute and its arguments
-    #[structopt(default_value = "/bin/bash")]
+    /// The default gateway for a subnet instead of the host's own address
+    /// within it (may be repeated).
+    #[structopt(long = "gateway", number_of_values = 1)]
+    gateways: Vec<IpAddr>,
+
+    /// An additional route, as SUBNET[@GATEWAY] or SUBNET@KIND to discard its
+    /// traffic (blackhole, unreachable or prohibit) (may be repeated).
+    #[structopt(long = "route", number_of_values = 1)]
+    routes: Vec<Route>,
+
+    /// A specific address to use instead of a random one (may be repeated).
+    #[structopt(short, long = "address", number_of_values = 1)]
+    addresses: Vec<Request>,
+
+    /// The number of addresses to assign from each subnet (default: 1).
+    #[structopt(long)]
+    count: Option<usize>,
+
+    /// Seed the choice of addresses, making it reproducible.
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// The interface name template ({n}, {parent} and {subnet} are expanded).
+    #[structopt(long)]
+    ifname: Option<String>,
+
+    /// The interface name prefix (shorthand for `--ifname <prefix>{n}`).
+    #[structopt(long, conflicts_with = "ifname")]
+    ifname_prefix: Option<String>,
+
+    /// The driver for the created interfaces (ipvlan, macvlan or veth).
+    #[structopt(long, default_value = "ipvlan")]
+    driver: Driver,
+
+    /// The ipvlan mode (l2, l3 or l3s; default: l3s).
+    #[structopt(long)]
+    mode: Option<IpvlanMode>,
+
+    /// The macvlan mode (private, vepa, bridge or passthru).
+    #[structopt(long, default_value = "bridge")]
+    macvlan_mode: MacvlanMode,
+
+    /// The hardware address of macvlan interfaces (random, stable or an
+    /// address, which is root only; default: random).
+    #[structopt(long)]
+    mac: Option<mac::Policy>,
+
+    /// The bridge for the host end of veth pairs (defaults to the parent).
+    #[structopt(long)]
+    bridge: Option<String>,
+
+    /// The MTU of the created interfaces (defaults to the parent's MTU).
+    #[structopt(long)]
+    mtu: Option<u32>,
+
+    /// Set the hostname in a new UTS namespace.
+    #[structopt(long)]
+    hostname: Option<String>,
+
+    /// Derive the hostname from the first assigned address (e.g. ip-10-0-3-17).
+    #[structopt(long, conflicts_with = "hostname")]
+    hostname_from_address: bool,
+
+    /// Persist the namespace as /var/run/netns/<name>.
+    #[structopt(long)]
+    name: Option<String>,
+
+    /// Execute the command as this user (root only).
+    #[structopt(long)]
+    user: Option<String>,
+
+    /// Execute the command with this uid (root only).
+    #[structopt(long)]
+    uid: Option<u32>,
+
+    /// Execute the command with this gid (root only).
+    #[structopt(long)]
+    gid: Option<u32>,
+
+    /// Execute the command with these supplementary groups (root only).
+    #[structopt(long, use_delimiter = true)]
+    groups: Vec<String>,
+
+    /// Clear the environment of the executed command.
+    #[structopt(long)]
+    clearenv: bool,
+
+    /// Set an environment variable (KEY=VALUE) for the command (may be repeated).
+    #[structopt(long, parse(try_from_str = parse_pair))]
+    env: Vec<(String, String)>,
+
+    /// Keep an environment variable despite --clearenv (may be repeated).
+    #[structopt(long)]
+    preserve_env: Vec<String>,
+
+    /// The working directory of the executed command.
+    #[structopt(long)]
+    chdir: Option<PathBuf>,
+
+    /// Wait for the command (instead of executing it directly) and exit with
+    /// its status, cleaning up afterwards.
+    #[structopt(long)]
+    supervise: bool,
+
+    /// Forward a host port to the command, as HOSTPORT:PORT[/tcp|udp]
+    /// (may be repeated; requires --supervise or daemon).
+    #[structopt(long, number_of_values = 1)]
+    publish: Vec<publish::Publish>,
+
+    /// Write the pid of the command and the path of its namespace to a file
+    /// (removed on exit with --supervise).
+    #[structopt(long)]
+    pid_file: Option<PathBuf>,
+
+    /// Give up waiting for other invocations after this many seconds.
+    #[structopt(long)]
+    lock_timeout: Option<u64>,
+
+    /// Only avoid the addresses recorded by ipvlan instead of scanning every
+    /// network namespace (root only).
+    #[structopt(long)]
+    no_scan: bool,
+
+    /// Terminate the command after this many seconds (requires --supervise).
+    #[structopt(long, requires = "supervise")]
+    timeout: Option<u64>,
+
+    /// Attach a label to the namespace, as KEY=VALUE (may be repeated).
+    #[structopt(long = "label", number_of_values = 1, parse(try_from_str = parse_pair))]
+    labels: Vec<(String, String)>,
+
+    /// Print the assigned addresses before executing the command.
+    #[structopt(long)]
+    print_addrs: bool,
+
+    /// Print the assigned addresses as JSON (requires --print-addrs).
+    #[structopt(long, requires = "print-addrs")]
+    json: bool,
+
+    /// Only assign IPv4 addresses.
+    #[structopt(long, conflicts_with = "ipv6-only")]
+    ipv4_only: bool,
+
+    /// Only assign IPv6 addresses.
+    #[structopt(long)]
+    ipv6_only: bool,
+
+    /// A DNS server for the command (may be repeated).
+    #[structopt(long, number_of_values = 1)]
+    dns: Vec<IpAddr>,
+
+    /// A DNS search domain for the command (may be repeated).
+    #[structopt(long, number_of_values = 1)]
+    dns_search: Vec<String>,
+
+    /// Don't set up the loopback interface.
+    #[structopt(long)]
+    no_loopback: bool,
+
+    /// Print the planned setup without creating anything.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// The binary to execute and its arguments (default: the configured
+    /// command or /bin/bash).
     argv: Vec<String>,
+
+    #[structopt(subcommand)]
+    action: Option<Action>,
 }
 
-fn main() -> Result<()> {
-    const LO_ADDR6: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
-    const LO_ADDR4: [u8; 4] = [127, 0, 0, 1];
+/// Parses a `KEY=VALUE` pair (an environment variable or a label)
+fn parse_pair(s: &str) -> std::result::Result<(String, String), String> {
+    let mut split = s.splitn(2, '=');
+    match (split.next(), split.next()) {
+        (Some(key), Some(value)) if !key.is_empty() => Ok((key.into(), value.into())),
+        _ => Err(format!("expected KEY=VALUE: {}", s)),
+    }
+}
 
-    // Parse our arguments.
-    let options = Options::from_args();
+/// Parses an `INTERFACE=SUBNET` parent selection
+fn parse_parent(s: &str) -> std::result::Result<(String, Subnet), String> {
+    let mut split = s.splitn(2, '=');
+    match (split.next(), split.next()) {
+        (Some(parent), Some(subnet)) if !parent.is_empty() => Ok((
+            parent.into(),
+            subnet.parse().map_err(|e| format!("{}: {}", s, e))?,
+        )),
+        _ => Err(format!("invalid parent: {}", s)),
+    }
+}
 
-    // Validate our capabilities.
-    let permitted = caps::read(None, CapSet::Permitted)?;
-    let effective = caps::read(None, CapSet::Effective)?;
-    assert!(permitted.contains(&Capability::CAP_DAC_OVERRIDE));
-    assert!(permitted.contains(&Capability::CAP_NET_ADMIN));
-    assert!(permitted.contains(&Capability::CAP_SYS_ADMIN));
-    assert_eq!(permitted.len(), 3);
-    assert!(effective.is_empty());
-
-    // Open and lock the configuration file.
-    let conf = File::open(options.config)?;
-    flock(&conf, libc::LOCK_EX)?;
-
-    // Validate configuration file permissions.
-    let md = conf.metadata()?;
-    //assert_eq!(md.dev(), File::open("/proc/self/exe")?.metadata()?.dev());
-    assert_eq!(md.uid(), 0); // Must be owned by root.
-    let mut mode = md.mode();
-    mode &= 0o7777;
-    mode &= !0o0444; // Remove read bits
-    mode &= !0o0200; // Remove owner write bit.
-    assert_eq!(mode, 0o0000);
-
-    // Parse the configuration file.
-    let mut conf = BufReader::new(conf);
-    let subnets = load_config(&mut conf)?;
+/// Builds the command to execute with its environment
+///
+/// The addresses in `links` are exported as `IPVLAN_{IFACE,ADDR,PREFIX,GATEWAY}_<n>`
+/// and, all together, as `IPVLAN_JSON`.
+fn command(
+    program: &program::Executable,
+    argv: &[String],
+    options: &Options,
+    links: &[state::Link],
+) -> Result<Command> {
+    let mut command = program.command();
+    command.arg0(&argv[0]).args(&argv[1..]);
 
-    // Collect the interfaces we want to vlan and their gateway addresses.
-    let mut ipvlans = HashMap::<Interface, Vec<Address>>::new();
-    for subnet in &subnets {
-        let gateway = Address::list()?
-            .into_iter()
-            .find(|x| x.subnet() == *subnet)
-            .unwrap_or_else(|| panic!("unable to find gateway for {}", subnet));
+    if options.clearenv {
+        command.env_clear();
+        for key in &options.preserve_env {
+            if let Some(value) = std::env::var_os(key) {
+                command.env(key, value);
+            }
+        }
+    }
 
-        ipvlans
-            .entry(gateway.interface()?)
-            .and_modify(|x| x.push(gateway))
-            .or_insert_with(|| vec![gateway]);
+    // Describe the network, numbering the addresses across all interfaces.
+    let assignments = links
+        .iter()
+        .flat_map(|l| l.addresses.iter().map(move |a| (&l.name, a)));
+    for (i, (name, a)) in assignments.enumerate() {
+        command.env(format!("IPVLAN_IFACE_{}", i), name);
+        command.env(format!("IPVLAN_ADDR_{}", i), a.address.to_string());
+        command.env(format!("IPVLAN_PREFIX_{}", i), a.prefix.to_string());
+        command.env(format!("IPVLAN_GATEWAY_{}", i), a.gateway.to_string());
     }
-    let mut ipvlans: Vec<(Interface, Vec<Address>)> = ipvlans.into_iter().collect();
+    command.env("IPVLAN_JSON", serde_json::to_string(links)?);
 
-    // Scan for in-use ip addresses.
-    let used = scan_namespaces(subnets)?;
+    for (key, value) in &options.env {
+        command.env(key, value);
+    }
 
-    // Set up the namespaces.
-    let oldns = File::open("/proc/self/ns/net")?;
-    unshare(libc::CLONE_NEWNET)?;
-    let newns = File::open("/proc/self/ns/net")?;
-    setns(&oldns, libc::CLONE_NEWNET)?;
+    if let Some(dir) = &options.chdir {
+        command.current_dir(dir);
+    }
 
-    // Create our macvlan interfaces in the new namespace.
-    for (i, (interface, _)) in ipvlans.iter_mut().enumerate() {
-        let name = format!("ipvl{}", i);
-        caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
-            let ipvlan = interface.add_ipvlan(&name)?;
-            match ipvlan.move_to_namespace(&newns) {
-                Ok(..) => Ok(()),
-                Err((ipvlan, error)) => {
-                    ipvlan.delete().unwrap();
-                    Err(error.into())
-                }
+    // The dynamic loader would otherwise run code besides the allowed program.
+    if program.checked() {
+        let inherited = std::env::vars_os().map(|(key, _)| key);
+        let requested = options.env.iter().map(|(key, _)| key.into());
+        for key in inherited.chain(requested) {
+            if key.as_bytes().starts_with(b"LD_") {
+                command.env_remove(key);
             }
-        })?;
+        }
     }
 
-    // Swap to the new namespace.
-    setns(&newns, libc::CLONE_NEWNET)?;
-    drop(oldns);
-    drop(newns);
+    Ok(command)
+}
 
-    caps::drop(None, CapSet::Permitted, Capability::CAP_SYS_ADMIN)?;
+/// Undoes the setup that doesn't die with the namespace's last process
+fn teardown(record: &state::Record) -> Result<()> {
+    if record.name.is_none() {
+        record.remove()?;
+    }
 
-    // Bring up the new ipvlan interfaces.
-    for (i, (_, gateways)) in ipvlans.iter().enumerate() {
-        let name = format!("ipvl{}", i);
+    Ok(())
+}
 
-        for gateway in gateways {
-            let subnet = gateway.subnet();
-            let address = loop {
-                let proposed = subnet.random();
-                if !used.contains(&proposed) {
-                    break proposed;
-                }
-            };
+/// Forwards the published ports to the first assigned address
+///
+/// This must be called from within the namespace.
+fn forward(listeners: Vec<publish::Listener>, record: &state::Record) {
+    if let Some(a) = record.links.iter().flat_map(|l| l.addresses.iter()).next() {
+        for listener in listeners {
+            listener.forward(a.address);
+        }
+    }
+}
 
-            let mut ipvlan = Interface::find(&name)?;
-            caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
-                ipvlan.add_address(address, subnet.prefix())?;
-                ipvlan.up()?;
-                ipvlan.add_gateway(gateway.address())?;
-                Ok(())
-            })?
+/// Re-reads the configuration files named by the options
+fn reload(options: &Options) -> Result<config::Config> {
+    let lock_timeout = options.lock_timeout.map(Duration::from_secs);
+    let mut config = config::Config::default();
+
+    for path in config_paths(options)? {
+        if path.as_os_str() == config::STDIN {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "standard input can't be read again",
+            ));
+        }
+
+        let file = BufReader::new(config::open(&path, lock_timeout)?);
+        let format = options
+            .config_format
+            .unwrap_or_else(|| config::Format::detect(&path));
+        config.merge(config::read(format, file).context(|| path.display().to_string())?);
+    }
+
+    config.select(options.profile.as_deref())
+}
+
+/// Holds the namespace open until `SIGTERM` or `SIGINT`, then tears down
+/// and exits
+///
+/// On `SIGHUP`, the configuration is read again, instead of tearing down. The
+/// namespace keeps its interfaces and addresses: only the programs allowed
+/// within it and the locked command (see `lock-command`) are updated. Changes
+/// to the subnets (and who is allowed to use them) only apply to namespaces
+/// set up afterwards.
+fn hold(
+    listeners: Vec<publish::Listener>,
+    mut record: state::Record,
+    options: &Options,
+) -> Result<()> {
+    let pid_file = options.pid_file.as_deref();
+
+    for cap in USER_CAPS {
+        caps::drop(None, CapSet::Permitted, *cap)?;
+    }
+
+    // Block the signals before spawning any threads, so all wait on them here.
+    let mut signal = 0;
+    let mut set = std::mem::MaybeUninit::<libc::sigset_t>::uninit();
+    let set = unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        for signal in &[libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+            libc::sigaddset(set.as_mut_ptr(), *signal);
         }
+        set.assume_init()
+    };
+
+    match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) } {
+        0 => (),
+        errno => return Err(Error::from_raw_os_error(errno)),
     }
 
-    // Bring up the loopback interface.
-    let mut ipvlan = Interface::find("lo")?;
-    caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
-        ipvlan.add_address(IpAddr::V6(LO_ADDR6.into()), 128)?;
-        ipvlan.add_address(IpAddr::V4(LO_ADDR4.into()), 8)?;
-        ipvlan.up()?;
-        Ok(())
-    })?;
+    forward(listeners, &record);
 
-    caps::drop(None, CapSet::Permitted, Capability::CAP_NET_ADMIN)?;
+    if let Some(path) = pid_file {
+        write_pid_file(path, std::process::id(), &record)?;
+    }
 
-    // Release the lock and execute.
-    drop(conf);
-    Err(Command::new(&options.argv[0])
-        .args(&options.argv[1..])
-        .exec())
+    info!("holding namespace: pid={}", std::process::id());
+    loop {
+        match unsafe { libc::sigwait(&set, &mut signal) } {
+            0 => info!("received signal: signal={}", signal),
+            errno => return Err(Error::from_raw_os_error(errno)),
+        }
+
+        if signal != libc::SIGHUP {
+            break;
+        }
+
+        match reload(options) {
+            Ok(config) => {
+                info!("reloaded configuration: subnets={}", config.subnets.len());
+                record.programs = config.programs;
+                record.command = if config.lock_command {
+                    Some(
+                        config
+                            .command
+                            .unwrap_or_else(|| vec![DEFAULT_COMMAND.into()]),
+                    )
+                } else {
+                    None
+                };
+                record.save()?;
+            }
+
+            Err(error) => warn!("unable to reload configuration: error={}", error),
+        }
+    }
+
+    if let Some(path) = pid_file {
+        std::fs::remove_file(path)?;
+    }
+
+    teardown(&record)
+}
+
+/// Writes the pid of the command and the path of its namespace to `path`
+fn write_pid_file(path: &Path, pid: u32, record: &state::Record) -> Result<()> {
+    let namespace = match &record.name {
+        Some(name) => netns::path(name)?,
+        None => PathBuf::from(format!("/proc/{}/ns/net", pid)),
+    };
+
+    info!("writing pid file: path={} pid={}", path.display(), pid);
+    std::fs::write(path, format!("{}\n{}\n", pid, namespace.display()))
+}
+
+/// Runs `command` as a child, tears down and exits with the child's status
+///
+/// The published ports are only forwarded (from `listeners`) once the child
+/// is running, since no threads may exist while it is forked.
+///
+/// A child killed by a signal is reported as `128 + signal`, like a shell.
+///
+/// If `timeout` expires, the child is sent `SIGTERM` and, if it is still
+/// running after `GRACE_PERIOD`, `SIGKILL`.
+fn supervise(
+    mut command: Command,
+    identity: Option<user::Identity>,
+    listeners: Vec<publish::Listener>,
+    record: &state::Record,
+    timeout: Option<Duration>,
+    pid_file: Option<&Path>,
+) -> Result<()> {
+    if let Some(identity) = &identity {
+        info!(
+            "switching user: uid={} gid={} groups={:?}",
+            identity.uid, identity.gid, identity.groups
+        );
+    }
+
+    // The supervisor keeps capabilities for teardown; the child must not.
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(identity) = &identity {
+                identity.apply()?;
+            }
+
+            caps::clear(None, CapSet::Permitted)?;
+            Ok(())
+        });
+    }
+
+    info!("spawning: command={:?}", command);
+    let mut child = command.spawn()?;
+    for cap in USER_CAPS {
+        caps::drop(None, CapSet::Permitted, *cap)?;
+    }
+
+    forward(listeners, record);
+
+    if let Some(path) = pid_file {
+        write_pid_file(path, child.id(), record)?;
+    }
+
+    // Like system(), leave terminal interrupts to the child.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+    }
+
+    let status = match timeout {
+        None => child.wait()?,
+        Some(timeout) => {
+            let term = Instant::now() + timeout;
+            let kill = term + GRACE_PERIOD;
+            let mut terminated = false;
+
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+
+                let now = Instant::now();
+                if now >= kill {
+                    info!("killing child: pid={}", child.id());
+                    child.kill()?;
+                    break child.wait()?;
+                } else if now >= term && !terminated {
+                    info!("terminating child: pid={}", child.id());
+                    if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } == -1 {
+                        return Err(Error::last_os_error());
+                    }
+                    terminated = true;
+                }
+
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+    };
+
+    info!("child exited: status={}", status);
+    if let Some(path) = pid_file {
+        std::fs::remove_file(path)?;
+    }
+    teardown(record)?;
+
+    std::process::exit(match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    })
+}
+
+/// Joins a persistent namespace created by `--name` and executes `argv`
+fn attach(name: &str, argv: &[String], options: &Options) -> Result<()> {
+    let record = state::Record::load(name)?;
+    info!("attaching: name={}", name);
+    join(&record, argv, options)
+}
+
+/// Joins a namespace, by name or by the pid holding it, and executes `argv`
+fn exec(target: &str, argv: &[String], options: &Options) -> Result<()> {
+    let record = match target.parse() {
+        Ok(pid) => state::Record::load_pid(pid)?,
+        Err(..) => state::Record::load(target)?,
+    };
+
+    info!("joining: target={}", target);
+    join(&record, argv, options)
+}
+
+/// Joins the namespace of `record` and executes `argv` without privileges
+fn join(record: &state::Record, argv: &[String], options: &Options) -> Result<()> {
+    let uid = unsafe { libc::getuid() };
+    if uid != 0 && uid != record.uid {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "the namespace belongs to another user",
+        ));
+    }
+
+    if let Some(command) = &record.command {
+        if uid != 0 && command.as_slice() != argv {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("only the configured command can be executed: {:?}", command),
+            ));
+        }
+    }
+
+    let path = match &record.name {
+        Some(name) => netns::path(name)?,
+        None => PathBuf::from(format!("/proc/{}/ns/net", record.pid)),
+    };
+
+    // Make sure the pid wasn't reused by an unrelated process.
+    let ns = caps::with(Capability::CAP_DAC_OVERRIDE, || File::open(&path))?;
+    let md = ns.metadata()?;
+    if (md.dev(), md.ino()) != record.namespace {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "the namespace no longer exists",
+        ));
+    }
+
+    setns(&ns, libc::CLONE_NEWNET)?;
+    drop(ns);
+
+    caps::clear(None, CapSet::Permitted)?;
+
+    let program = program::check(&record.programs, argv)?;
+    info!("executing: argv={:?}", argv);
+    Err(command(&program, argv, options, &record.links)?.exec())
+}
+
+/// Prints the namespaces created by ipvlan
+fn list(json: bool, labels: &[(String, String)]) -> Result<()> {
+    let mut records = state::Record::list()?;
+    records.retain(|r| labels.iter().all(|(k, v)| r.labels.get(k) == Some(v)));
+
+    if json {
+        let mut values = Vec::new();
+        for record in &records {
+            let mut value = serde_json::to_value(record)?;
+            value["alive"] = record.alive().into();
+            values.push(value);
+        }
+
+        println!("{}", serde_json::to_string_pretty(&values)?);
+        return Ok(());
+    }
+
+    for record in &records {
+        println!(
+            "{} pid {} uid {} ({}): {}",
+            record.name.as_deref().unwrap_or("-"),
+            record.pid,
+            record.uid,
+            if record.alive() { "alive" } else { "dead" },
+            record.argv.join(" ")
+        );
+
+        for (key, value) in &record.labels {
+            println!("    label {}={}", key, value);
+        }
+
+        for link in &record.links {
+            for assignment in &link.addresses {
+                println!(
+                    "    {} {}/{} via {}",
+                    link.name, assignment.address, assignment.prefix, assignment.gateway
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds (or creates) the 802.1Q sub-interface `id` of `parent`
+///
+/// The sub-interface is left in place for later invocations. A dry run
+/// doesn't create it, but plans on `parent` instead.
+fn vlan(parent: &str, id: u16, dry_run: bool) -> Result<String> {
+    let name = format!("{}.{}", parent, id);
+    if Interface::find(&name).is_ok() {
+        return Ok(name);
+    }
+
+    if dry_run {
+        info!("would create vlan: parent={} name={}", parent, name);
+        return Ok(parent.into());
+    }
+
+    let mut base = Interface::find(parent).context(|| format!("parent {}", parent))?;
+    info!("creating vlan: parent={} name={} id={}", parent, name, id);
+    caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+        let vlan = base.add_vlan(&name, id)?;
+        Ok(vlan.up()?)
+    })
+    .context(|| format!("unable to create vlan {}", name))?;
+
+    Ok(name)
+}
+
+/// Finds the configuration files named by the options
+fn config_paths(options: &Options) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for path in &options.config {
+        paths.extend(config::expand(path)?);
+    }
+
+    if options.config.is_empty() && options.subnets.is_empty() {
+        paths.push(config::DEFAULT.into());
+        if Path::new(config::DEFAULT_DIR).is_dir() {
+            paths.extend(config::expand(Path::new(config::DEFAULT_DIR))?);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Checks the configuration files, reporting every problem found
+fn validate(options: &Options) -> Result<()> {
+    let lock_timeout = options.lock_timeout.map(Duration::from_secs);
+    let mut problems = Vec::new();
+
+    let mut config = config::Config::default();
+    for path in config_paths(options)? {
+        let parsed = config::open(&path, lock_timeout).and_then(|file| {
+            let format = options
+                .config_format
+                .unwrap_or_else(|| config::Format::detect(&path));
+            config::read(format, BufReader::new(file))
+        });
+
+        match parsed {
+            Ok(parsed) => config.merge(parsed),
+            Err(error) => problems.push(format!("{}: {}", path.display(), error)),
+        }
+    }
+
+    let config = match config.clone().select(options.profile.as_deref()) {
+        Ok(selected) => selected,
+        Err(error) => {
+            problems.push(error.to_string());
+            config
+        }
+    };
+
+    // Addresses in overlapping subnets could be assigned twice.
+    let subnets: Vec<Subnet> = config.subnets.keys().copied().collect();
+    for (i, a) in subnets.iter().enumerate() {
+        for b in &subnets[i + 1..] {
+            if a.contains(b.address()) || b.contains(a.address()) {
+                problems.push(format!("{} overlaps {}", a, b));
+            }
+        }
+    }
+
+    // Each subnet needs a parent interface on this host.
+    let addresses = Address::list().context(|| "unable to list addresses".into())?;
+    for (subnet, settings) in &config.subnets {
+        let mut candidates = addresses.iter().filter(|a| a.subnet() == *subnet);
+        let found = match (&settings.parent, settings.gateway) {
+            (Some(name), Some(..)) => Interface::find(name).is_ok(),
+            (Some(name), None) => {
+                candidates.any(|a| a.interface().map_or(false, |i| i.to_string() == *name))
+            }
+            (None, _) => candidates.next().is_some(),
+        };
+
+        if !found {
+            problems.push(format!("{}: no parent interface found", subnet));
+        }
+    }
+
+    if problems.is_empty() {
+        println!("configuration ok");
+        return Ok(());
+    }
+
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!("{} problem(s) found", problems.len()),
+    ))
+}
+
+/// Removes stale records and (optionally) unused persistent namespaces
+fn cleanup(unused: bool) -> Result<()> {
+    let uid = unsafe { libc::getuid() };
+    let used = caps::with(Capability::CAP_DAC_OVERRIDE, process_namespaces)?;
+
+    for record in state::Record::list()? {
+        if !record.alive() {
+            if !dry_run {
+                info!("removing stale record: pid={}", record.pid);
+                record.remove()?;
+            }
+            continue;
+        }
+
+        let name = match &record.name {
+            Some(name) if unused => name,
+            _ => continue,
+        };
+
+        if used.contains_key(&record.namespace) || (uid != 0 && uid != record.uid) {
+            continue;
+        }
+
+        // Delete the interfaces we created before releasing the namespace.
+        info!("removing unused namespace: name={}", name);
+        let path = netns::path(name)?;
+        let saved = File::open("/proc/self/ns/net")?;
+        setns(&File::open(&path)?, libc::CLONE_NEWNET)?;
+        let deleted = record
+            .links
+            .iter()
+            .try_for_each(|link| match Interface::find(&link.name) {
+                Ok(interface) => caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+                    interface.delete().map_err(|(_, e)| e)?;
+                    Ok(())
+                }),
+                Err(..) => Ok(()),
+            });
+
+        // Return to our namespace whether or not the interfaces were deleted.
+        setns(&saved, libc::CLONE_NEWNET)?;
+        deleted?;
+
+        netns::remove(&path)?;
+        record.remove()?;
+    }
+
+    Ok(())
+}
+
+/// The temporary name of the `n`th interface, used until it is in the new
+/// namespace (so it can't clash with the host's interfaces)
+fn temporary(n: usize) -> String {
+    format!("ipvl{}.{}", std::process::id(), n)
+}
+
+/// The command executed when neither the command line nor the configuration
+/// gives one
+const DEFAULT_COMMAND: &str = "/bin/bash";
+
+/// How many random addresses to try before giving up on a subnet
+const MAX_ATTEMPTS: usize = 10_000;
+
+/// The name of the dummy interface holding extra addresses
+const DUMMY: &str = "dummy0";
+
+/// How long a timed out command has to exit after `SIGTERM`
+const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// How often a command with a timeout is checked for exit
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The capabilities we must have in the permitted set
+const REQUIRED_CAPS: &[Capability] = &[
+    Capability::CAP_DAC_OVERRIDE,
+    Capability::CAP_NET_ADMIN,
+    Capability::CAP_SYS_ADMIN,
+];
+
+/// The capabilities we may have in the permitted set
+///
+/// `CAP_CHOWN` gives our files to root (see `state::chown()`); the others are
+/// only needed for `--user` (see `USER_CAPS`).
+const OPTIONAL_CAPS: &[Capability] = &[
+    Capability::CAP_CHOWN,
+    Capability::CAP_SETGID,
+    Capability::CAP_SETUID,
+];
+
+/// The capabilities needed to execute the command as another user
+const USER_CAPS: &[Capability] = &[Capability::CAP_SETGID, Capability::CAP_SETUID];
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("ipvlan: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    const LO_ADDR6: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const LO_ADDR4: [u8; 4] = [127, 0, 0, 1];
+
+    // Parse our arguments.
+    let options = Options::from_args();
+
+    // Set up logging.
+    env_logger::Builder::new()
+        .filter_level(match options.verbose {
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        })
+        .parse_default_env()
+        .init();
+
+    // Completions need no privileges, so handle them before checking any.
+    if let Some(Action::Completions { shell }) = options.action {
+        Options::clap().gen_completions_to("ipvlan", shell, &mut std::io::stdout());
+        return Ok(());
+    }
+
+    // Root has every capability, so keep only the ones we may need.
+    let uid = unsafe { libc::getuid() };
+    if uid == 0 {
+        let wanted: HashSet<Capability> =
+            REQUIRED_CAPS.iter().chain(OPTIONAL_CAPS).copied().collect();
+        let permitted = caps::read(None, CapSet::Permitted)?;
+        caps::clear(None, CapSet::Effective)?;
+        caps::set(None, CapSet::Permitted, &(&permitted & &wanted))?;
+    }
+
+    // Validate our capabilities.
+    let permitted = caps::read(None, CapSet::Permitted)?;
+    let effective = caps::read(None, CapSet::Effective)?;
+    if let Some(cap) = REQUIRED_CAPS.iter().find(|c| !permitted.contains(c)) {
+        return Err(error::Error::MissingCapability(*cap).into());
+    }
+    let allowed = |c: &&Capability| REQUIRED_CAPS.contains(c) || OPTIONAL_CAPS.contains(c);
+    if let Some(cap) = permitted.iter().find(|c| !allowed(c)) {
+        return Err(error::Error::ExcessCapability(*cap).into());
+    }
+    if let Some(cap) = effective.iter().next() {
+        return Err(error::Error::ExcessCapability(*cap).into());
+    }
+    debug!("capabilities: permitted={:?}", permitted);
+
+    // Only root may execute the command as another user.
+    let identity = user::Identity::resolve(
+        options.user.as_deref(),
+        options.uid,
+        options.gid,
+        &options.groups,
+    )?;
+    if identity.is_some() && uid != 0 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "only root may change the user",
+        ));
+    }
+
+    if identity.is_none() {
+        for cap in USER_CAPS {
+            caps::drop(None, CapSet::Permitted, *cap)?;
+        }
+    }
+
+    // Handle subcommands.
+    match &options.action {
+        Some(Action::Attach { name, argv }) => return attach(name, argv, &options),
+        Some(Action::Exec { target, argv }) => return exec(target, argv, &options),
+        Some(Action::List { json, labels }) => return list(*json, labels),
+        Some(Action::Status { json }) => return status::status(*json),
+        Some(Action::Cleanup { unused }) => return cleanup(*unused),
+        Some(Action::Validate) => return validate(&options),
+        Some(Action::Completions { .. }) => unreachable!(),
+        Some(Action::Daemon) | None => (),
+    }
+
+    if options.mac.is_some() && !matches!(options.driver, Driver::Macvlan) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--mac requires --driver macvlan",
+        ));
+    }
+
+    // Only a resident ipvlan can clean up or forward ports.
+    let daemon = matches!(options.action, Some(Action::Daemon));
+    let resident = daemon || options.supervise;
+    if !options.publish.is_empty() && !resident {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--publish requires --supervise or daemon",
+        ));
+    }
+
+    // Find the configuration files.
+    let paths = config_paths(&options)?;
+
+    // Skipping the scan could assign addresses used by others.
+    if options.no_scan && uid != 0 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "only root may skip the namespace scan",
+        ));
+    }
+
+    // A fixed hardware address could clash with (or impersonate) another host.
+    if let Some(mac::Policy::Fixed(..)) = options.mac {
+        if uid != 0 {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "only root may choose a fixed hardware address",
+            ));
+        }
+    }
+
+    // Only root may specify subnets on the command line.
+    if !options.subnets.is_empty() && uid != 0 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "only root may specify subnets on the command line",
+        ));
+    }
+
+    // Standard input and the command line can't be locked, so lock the
+    // state directory instead.
+    let lock_timeout = options.lock_timeout.map(Duration::from_secs);
+    let lock = if paths.is_empty() || paths.iter().any(|p| p.as_os_str() == config::STDIN) {
+        Some(state::lock(lock_timeout)?)
+    } else {
+        None
+    };
+
+    // Open, lock and validate the configuration files in a stable order.
+    let mut order: Vec<&PathBuf> = paths.iter().collect();
+    order.sort();
+    order.dedup();
+    let mut conf = HashMap::new();
+    for path in order {
+        conf.insert(path, config::open(path, lock_timeout)?);
+    }
+
+    // Parse the configuration files.
+    let mut config = config::Config::default();
+    for path in &paths {
+        let file = BufReader::new(&conf[path]);
+        let format = options
+            .config_format
+            .unwrap_or_else(|| config::Format::detect(path));
+        config.merge(config::read(format, file).context(|| path.display().to_string())?);
+    }
+    let mut config = config.select(options.profile.as_deref())?;
+    for subnet in &options.subnets {
+        config.subnets.entry(*subnet).or_default();
+    }
+
+    // Choose the command and check the program before setting anything up.
+    let configured = config
+        .command
+        .clone()
+        .unwrap_or_else(|| vec![DEFAULT_COMMAND.into()]);
+    let argv = if options.argv.is_empty() {
+        configured.clone()
+    } else if config.lock_command && uid != 0 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "the configured command can't be overridden",
+        ));
+    } else {
+        options.argv.clone()
+    };
+    let program = if daemon {
+        program::Executable::default()
+    } else {
+        program::check(&config.programs, &argv)?
+    };
+
+    // Read the ruleset before setting anything up.
+    let ruleset = match &config.nftables {
+        Some(ruleset) => Some(ruleset.read().context(|| format!("ruleset {}", ruleset))?),
+        None => None,
+    };
+
+    // Restrict the setup to the requested address family.
+    let family = |address: &IpAddr| match address {
+        IpAddr::V4(..) => !options.ipv6_only,
+        IpAddr::V6(..) => !options.ipv4_only,
+    };
+    config.subnets.retain(|s, _| family(&s.address()));
+    config.loopback.retain(|(a, _)| family(a));
+    config.dummy.retain(|(a, _)| family(a));
+    config.routes.retain(|r| family(&r.destination().address()));
+
+    // Restrict the setup to the subnets we are allowed to use.
+    if uid != 0 {
+        let groups = user::groups()?;
+        let mut denied = Vec::new();
+        for (subnet, settings) in &config.subnets {
+            let mut allowed = settings.allow.is_empty();
+            for principal in &settings.allow {
+                allowed |= match principal {
+                    config::Principal::User(name) => user::uid(name).ok() == Some(uid),
+                    config::Principal::Group(name) => {
+                        user::group(name).map_or(false, |g| groups.contains(&g))
+                    }
+                };
+            }
+
+            if !allowed {
+                denied.push(*subnet);
+            }
+        }
+
+        for subnet in denied {
+            if options.addresses.iter().any(|r| r.within(&subnet)) {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("not allowed to use {}", subnet),
+                ));
+            }
+
+            debug!("skipping denied subnet: subnet={}", subnet);
+            config.subnets.remove(&subnet);
+        }
+    }
+
+    let subnets: HashSet<Subnet> = config.subnets.keys().copied().collect();
+
+    // Validate the requested parents.
+    for (name, subnet) in &options.parents {
+        if !subnets.contains(subnet) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} (parent {}) is not a configured subnet", subnet, name),
+            ));
+        }
+    }
+
+    // Validate the requested gateways.
+    for (i, gateway) in options.gateways.iter().enumerate() {
+        let subnet = subnets
+            .iter()
+            .find(|s| s.contains(*gateway))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not within a configured subnet", gateway),
+                )
+            })?;
+
+        if options.gateways[..i].iter().any(|g| subnet.contains(*g)) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("multiple gateways requested for {}", subnet),
+            ));
+        }
+    }
+
+    // Collect the interfaces we want to vlan and their gateway addresses.
+    let mut ipvlans = HashMap::<Interface, Vec<Address>>::new();
+    for subnet in &subnets {
+        let mut candidates = Address::list()
+            .context(|| "unable to list addresses".into())?
+            .into_iter()
+            .filter(|x| x.subnet() == *subnet);
+
+        // Prefer the command line parent and gateway, then the configured ones.
+        let settings = &config.subnets[subnet];
+        let name = options
+            .parents
+            .iter()
+            .find(|(_, s)| s == subnet)
+            .map(|(name, _)| name.as_str())
+            .or_else(|| settings.parent.as_deref());
+        let router = options
+            .gateways
+            .iter()
+            .copied()
+            .find(|a| subnet.contains(*a))
+            .or(settings.gateway);
+
+        // Build on the tagged sub-interface of the parent, if configured.
+        let tagged = match (settings.vlan, name) {
+            (Some(id), Some(name)) => Some(vlan(name, id, options.dry_run)?),
+            _ => None,
+        };
+        let name = tagged.as_deref().or(name);
+
+        // Prefer the address on the requested parent, if any.
+        let (gateway, parent) = match (name, router) {
+            // The parent needn't have an address within the subnet.
+            (Some(name), Some(router)) => {
+                let parent = Interface::find(name).context(|| format!("parent {}", name))?;
+                let gateway = Address::new(parent.index(), router, subnet.prefix());
+                (gateway, parent)
+            }
+
+            (None, _) => {
+                let gateway = candidates.next().ok_or(error::Error::NoGateway(*subnet))?;
+                (gateway, gateway.interface()?)
+            }
+
+            (Some(name), None) => {
+                let mut found = None;
+                for candidate in candidates {
+                    let parent = candidate.interface()?;
+                    if parent.to_string() == name {
+                        found = Some((candidate, parent));
+                        break;
+                    }
+                }
+
+                found.ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!("{} has no address within {}", name, subnet),
+                    )
+                })?
+            }
+        };
+
+        // Bridge ports and bond members don't carry their own traffic.
+        if let Some(index) = parent.master() {
+            let master = Interface::from_index(index)?;
+            if let Some("bridge") | Some("bond") | Some("team") = master.kind() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "parent {} is enslaved to {}, which should be the parent instead",
+                        parent, master
+                    ),
+                ));
+            }
+        }
+
+        // Route through the requested gateway rather than our own address.
+        let gateway = match router {
+            Some(router) => Address::new(parent.index(), router, subnet.prefix()),
+            None => gateway,
+        };
+
+        info!(
+            "found gateway: subnet={} gateway={} parent={}",
+            subnet,
+            gateway.address(),
+            parent
+        );
+
+        ipvlans
+            .entry(parent)
+            .and_modify(|x| x.push(gateway))
+            .or_insert_with(|| vec![gateway]);
+    }
+
+    // Scan for in-use ip addresses, unless the records are trusted.
+    let mut used = if options.no_scan || config.no_scan {
+        recorded_addresses(&subnets, options.dry_run)?
+    } else {
+        scan_namespaces(&subnets)?
+    };
+    used.extend(options.gateways.iter().copied());
+    used.extend(config.subnets.values().filter_map(|s| s.gateway));
+
+    // Resolve the owners of reserved addresses. The addresses belong to the
+    // user running the command.
+    let owner = identity.as_ref().map_or(uid, |i| i.uid);
+    let mut reserved = HashMap::<IpAddr, u32>::new();
+    for reservation in &config.reservations {
+        match user::uid(&reservation.user) {
+            Ok(uid) => {
+                reserved.insert(reservation.address, uid);
+            }
+
+            // Keep the address from everyone rather than fail every invocation.
+            Err(error) => {
+                warn!(
+                    "unable to resolve reservation: address={} user={} error={}",
+                    reservation.address, reservation.user, error
+                );
+                used.insert(reservation.address);
+            }
+        }
+    }
+
+    // Keep released addresses from their subnet's other users until their
+    // lease expires, and from everyone during the grace period.
+    let leases = if options.dry_run {
+        lease::peek()?
+    } else {
+        lease::list()?
+    };
+
+    let mut expired = HashSet::new();
+    for lease in leases {
+        let settings = match config
+            .subnets
+            .iter()
+            .find(|(s, _)| s.contains(lease.address))
+        {
+            Some((_, settings)) => settings,
+            None => continue,
+        };
+
+        let grace = settings.grace.unwrap_or_default();
+        let term = settings.lease.unwrap_or_default();
+        if lease.held(owner, grace, term) {
+            debug!(
+                "address leased: address={} uid={}",
+                lease.address, lease.uid
+            );
+            used.insert(lease.address);
+        } else if lease.expired(grace, term) {
+            expired.insert(lease.address);
+        }
+    }
+    if !expired.is_empty() && !options.dry_run {
+        lease::prune(|l| expired.contains(&l.address))?;
+    }
+
+    // Validate the requested addresses.
+    for request in &options.addresses {
+        if !subnets.iter().any(|s| request.within(s)) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not within a configured subnet", request),
+            ));
+        }
+
+        if used.contains(&request.address) {
+            return Err(Error::new(
+                ErrorKind::AddrInUse,
+                format!("{} is already in use", request.address),
+            ));
+        }
+
+        if reserved
+            .get(&request.address)
+            .map_or(false, |u| *u != owner)
+        {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} is reserved for another user", request.address),
+            ));
+        }
+
+        let excluded = config
+            .subnets
+            .iter()
+            .filter(|(s, _)| s.contains(request.address))
+            .flat_map(|(_, settings)| settings.exclude.iter())
+            .any(|r| r.contains(request.address));
+        let outside = config
+            .subnets
+            .iter()
+            .filter(|(s, _)| s.contains(request.address))
+            .filter_map(|(_, settings)| settings.range)
+            .any(|r| !r.contains(request.address));
+        let unassignable = subnets
+            .iter()
+            .filter(|s| s.contains(request.address))
+            .any(|s| !s.assignable(request.address));
+        if excluded || outside || unassignable {
+            return Err(Error::new(
+                ErrorKind::AddrNotAvailable,
+                format!("{} is excluded from allocation", request.address),
+            ));
+        }
+    }
+
+    // Choose the interface name template.
+    let template = match (&options.ifname, &options.ifname_prefix) {
+        (Some(template), _) => template.clone(),
+        (None, Some(prefix)) => format!("{}{{n}}", prefix),
+        (None, None) => "ipvl{n}".into(),
+    };
+
+    // Choose an address for each gateway.
+    let mut random = match options.seed {
+        Some(seed) => random::Random::new(seed),
+        None => random::Random::from_entropy()?,
+    };
+    let mut links = Vec::new();
+    for (i, (parent, gateways)) in ipvlans.into_iter().enumerate() {
+        let name = ifname(&template, i, &parent, &gateways)?;
+
+        // Prefer the command line mode, then the configured mode.
+        let mut modes = Vec::new();
+        for mode in gateways
+            .iter()
+            .filter_map(|g| config.subnets[&g.subnet()].mode)
+        {
+            if !modes.contains(&mode) {
+                modes.push(mode);
+            }
+        }
+        let mode = match (options.mode, &modes[..]) {
+            (Some(mode), _) => mode,
+            (None, []) => IpvlanMode::L3S,
+            (None, [mode]) => *mode,
+            (None, _) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("conflicting modes configured for subnets on {}", parent),
+                ))
+            }
+        };
+
+        // Prefer the command line MAC policy, then the configured one.
+        let policy = options
+            .mac
+            .or_else(|| {
+                gateways
+                    .iter()
+                    .find_map(|g| config.subnets[&g.subnet()].mac)
+            })
+            .unwrap_or(mac::Policy::Random);
+        let mac = match options.driver {
+            Driver::Macvlan => policy.address(&parent, &gateways[0].subnet(), owner),
+            _ => None,
+        };
+
+        // Prefer the command line MTU, then the smallest configured MTU.
+        let mtu = options
+            .mtu
+            .or_else(|| {
+                gateways
+                    .iter()
+                    .filter_map(|g| config.subnets[&g.subnet()].mtu)
+                    .min()
+            })
+            .unwrap_or_else(|| parent.mtu());
+        let mut addresses = Vec::new();
+        let mut defaults = Vec::new();
+
+        // Turn off the offloads any of the subnets needs off.
+        let mut disable = Vec::new();
+        for gateway in &gateways {
+            for offload in &config.subnets[&gateway.subnet()].disable {
+                if !disable.contains(offload) {
+                    disable.push(*offload);
+                }
+            }
+        }
+
+        // Cap the bandwidth at the lowest rate of the subnets.
+        let rate = gateways
+            .iter()
+            .filter_map(|g| config.subnets[&g.subnet()].rate)
+            .min();
+
+        for gateway in gateways {
+            let subnet = gateway.subnet();
+
+            // Route by default through each gateway, unless told otherwise.
+            let settings = &config.subnets[&subnet];
+            let via = Some(gateway.address());
+            if !settings.no_gateway && !defaults.iter().any(|r: &Route| r.gateway() == via) {
+                defaults.push(Route::default_via(gateway.address()).with_metric(settings.metric));
+            }
+
+            let requested: Vec<&Request> = options
+                .addresses
+                .iter()
+                .filter(|r| r.within(&subnet))
+                .collect();
+
+            // Prefer the command line count, then the configured count.
+            let count = options.count.or(config.subnets[&subnet].count).unwrap_or(1);
+            if requested.len() > count {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("too many addresses requested for {}", subnet),
+                ));
+            }
+
+            // Use the requested addresses, then the reserved ones, then fill
+            // up with random ones.
+            let mut chosen: Vec<IpAddr> = requested.iter().map(|r| r.address).collect();
+            for reservation in &config.reservations {
+                let address = reservation.address;
+                if chosen.len() < count
+                    && reserved[&address] == owner
+                    && subnet.contains(address)
+                    && !used.contains(&address)
+                    && !chosen.contains(&address)
+                {
+                    chosen.push(address);
+                }
+            }
+
+            let pool = settings.range.unwrap_or_else(|| subnet.into());
+            let strategy = settings.strategy.unwrap_or(allocate::Strategy::Random);
+            let mut allocator = strategy.allocator(pool, &mut random, owner);
+
+            // Consecutive proposals have tried everything after one lap.
+            let limit = match strategy {
+                allocate::Strategy::Random => MAX_ATTEMPTS as u128,
+                allocate::Strategy::Sequential | allocate::Strategy::Hash => pool.size(),
+            };
+            let mut attempts = 0;
+            while chosen.len() < count {
+                if attempts == limit {
+                    return Err(Error::new(
+                        ErrorKind::AddrNotAvailable,
+                        format!("unable to find a free address within {}", subnet),
+                    ));
+                }
+                attempts += 1;
+
+                let proposed = allocator.propose();
+                if subnet.assignable(proposed)
+                    && proposed != gateway.address()
+                    && !used.contains(&proposed)
+                    && !chosen.contains(&proposed)
+                    && !reserved.contains_key(&proposed)
+                    && !settings.exclude.iter().any(|r| r.contains(proposed))
+                {
+                    chosen.push(proposed);
+                }
+            }
+
+            for address in chosen {
+                debug!("chose address: address={} subnet={}", address, subnet);
+                addresses.push((address, gateway));
+            }
+        }
+
+        links.push(Link {
+            name,
+            parent,
+            mode,
+            mtu,
+            mac,
+            addresses,
+            defaults,
+            routes: Vec::new(),
+            disable,
+            rate,
+        });
+    }
+
+    // Assign each route to the interface reaching its gateway or, without
+    // one, to the first interface of the same address family. Special routes
+    // need no interface, but are installed along with the first one; without
+    // one, their destinations are unreachable anyway.
+    for route in options.routes.iter().chain(&config.routes) {
+        let ipv4 = route.destination().address().is_ipv4();
+        let link = links.iter_mut().find(|l| {
+            l.addresses.iter().any(|(a, g)| match route.gateway() {
+                Some(gateway) => g.subnet().contains(gateway),
+                None => a.is_ipv4() == ipv4,
+            })
+        });
+
+        match link {
+            Some(link) if !link.routes.contains(route) => link.routes.push(*route),
+            Some(..) => (),
+            None if route.kind() != RouteKind::Unicast => {
+                debug!("skipping route without interface: route={}", route)
+            }
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("no interface can carry the route {}", route),
+                ))
+            }
+        }
+    }
+
+    // Detect interface name collisions. The interfaces are created under
+    // temporary names, so the names used on the host don't matter.
+    let mut names: HashSet<&str> = ["lo", DUMMY].iter().copied().collect();
+    for link in &links {
+        if !names.insert(&link.name) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("interface name collision: {}", link.name),
+            ));
+        }
+    }
+
+    // Choose the hostname.
+    let hostname = match &options.hostname {
+        Some(hostname) => Some(hostname.clone()),
+        None if options.hostname_from_address => links
+            .iter()
+            .flat_map(|l| l.addresses.iter())
+            .next()
+            .map(|(a, _)| format!("ip-{}", a.to_string().replace(&['.', ':'][..], "-"))),
+        None => None,
+    };
+
+    // Prefer the command line DNS settings, then those of the subnets in use.
+    let mut dns = options.dns.clone();
+    let mut search = options.dns_search.clone();
+    if dns.is_empty() {
+        for (_, gateway) in links.iter().flat_map(|l| l.addresses.iter()) {
+            let settings = &config.subnets[&gateway.subnet()];
+            for server in &settings.dns {
+                if !dns.contains(server) {
+                    dns.push(*server);
+                }
+            }
+
+            for domain in &settings.search {
+                if !search.contains(domain) {
+                    search.push(domain.clone());
+                }
+            }
+        }
+    }
+
+    if dns.is_empty() && !search.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--dns-search requires --dns or dns servers for the subnets in use",
+        ));
+    }
+
+    // Stop here if we were only asked for the plan.
+    if options.dry_run {
+        let hostname = hostname.as_deref();
+        print_plan(&links, &config, &dns, &search, hostname, &options);
+        return Ok(());
+    }
+
+    // Set up the namespaces.
+    info!("creating network namespace");
+    let oldns = File::open("/proc/self/ns/net")?;
+    unshare(libc::CLONE_NEWNET)?;
+    let newns = File::open("/proc/self/ns/net")?;
+    setns(&oldns, libc::CLONE_NEWNET)?;
+
+    // Create our macvlan interfaces in the new namespace.
+    for (i, link) in links.iter_mut().enumerate() {
+        let name = &temporary(i);
+        let parent = &mut link.parent;
+        let mode = link.mode;
+        let mac = link.mac;
+        info!(
+            "creating {}: name={} temporary={} parent={}",
+            options.driver, link.name, name, parent
+        );
+        caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+            let ipvlan = match options.driver {
+                Driver::Ipvlan => parent.add_ipvlan(name, mode)?,
+                Driver::Macvlan => parent.add_macvlan(name, options.macvlan_mode, mac)?,
+                Driver::Veth => {
+                    let bridge = match &options.bridge {
+                        Some(bridge) => {
+                            Interface::find(bridge).context(|| format!("bridge {}", bridge))?
+                        }
+                        None => parent.clone(),
+                    };
+
+                    let host = format!("veth{}.{}", std::process::id(), i);
+                    let (mut host, peer) = Interface::add_veth(&host, name)?;
+                    match host.set_master(&bridge).and_then(|()| host.up()) {
+                        Ok(()) => peer,
+                        Err(error) => {
+                            if let Err((host, error)) = host.delete() {
+                                warn!("unable to delete {}: {}", host, error);
+                            }
+                            return Err(error.into());
+                        }
+                    }
+                }
+            };
+            match ipvlan.move_to_namespace(&newns) {
+                Ok(..) => Ok(()),
+                Err((ipvlan, error)) => {
+                    if let Err((ipvlan, error)) = ipvlan.delete() {
+                        warn!("unable to delete {}: {}", ipvlan, error);
+                    }
+                    Err(error.into())
+                }
+            }
+        })
+        .context(|| format!("unable to create {}", link.name))?;
+    }
+
+    // Persist the new namespace.
+    if let Some(name) = &options.name {
+        info!("persisting namespace: name={}", name);
+        let path = netns::create(name)?;
+        if let Err(error) = netns::bind(&newns, &path) {
+            netns::remove(&path)?;
+            return Err(error);
+        }
+    }
+
+    // Record the new namespace.
+    let md = newns.metadata()?;
+    let record = state::Record {
+        name: options.name.clone(),
+        uid: unsafe { libc::getuid() },
+        pid: std::process::id(),
+        argv: if daemon { Vec::new() } else { argv.clone() },
+        namespace: (md.dev(), md.ino()),
+        labels: options.labels.iter().cloned().collect(),
+        programs: config.programs.clone(),
+        command: if config.lock_command {
+            Some(configured)
+        } else {
+            None
+        },
+        links: links
+            .iter()
+            .map(|l| state::Link {
+                name: l.name.clone(),
+                addresses: l
+                    .addresses
+                    .iter()
+                    .map(|(address, gateway)| state::Assignment {
+                        address: *address,
+                        prefix: gateway.subnet().prefix(),
+                        gateway: gateway.address(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+    record.save()?;
+
+    // Configure DNS in a new mount namespace.
+    if !dns.is_empty() {
+        dns::configure(&dns, &search)?;
+    }
+
+    // Resident processes need CAP_DAC_OVERRIDE (and CAP_CHOWN, if permitted,
+    // for the lease database) to remove the record when done.
+    if !resident {
+        caps::drop(None, CapSet::Permitted, Capability::CAP_DAC_OVERRIDE)?;
+        caps::drop(None, CapSet::Permitted, Capability::CAP_CHOWN)?;
+    }
+
+    // Bind the published ports while still in the host namespace.
+    let mut listeners = Vec::new();
+    for publish in &options.publish {
+        listeners.push(publish.listen()?);
+    }
+
+    // Swap to the new namespace.
+    setns(&newns, libc::CLONE_NEWNET)?;
+    drop(oldns);
+    drop(newns);
+
+    // Set the hostname in a new UTS namespace.
+    if let Some(hostname) = &hostname {
+        info!("setting hostname: hostname={}", hostname);
+        unshare(libc::CLONE_NEWUTS)?;
+        sethostname(hostname)?;
+    }
+
+    caps::drop(None, CapSet::Permitted, Capability::CAP_SYS_ADMIN)?;
+
+    // Bring up the new ipvlan interfaces.
+    for (i, link) in links.iter().enumerate() {
+        let mut ipvlan = Interface::find(&temporary(i))?;
+        info!("renaming interface: from={} to={}", ipvlan, link.name);
+        caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+            Ok(ipvlan.rename(&link.name)?)
+        })
+        .context(|| format!("unable to rename {} to {}", ipvlan, link.name))?;
+
+        info!("setting mtu: interface={} mtu={}", link.name, link.mtu);
+        let mtu = caps::with(Capability::CAP_NET_ADMIN, || -> Result<u32> {
+            Ok(ipvlan.set_mtu(link.mtu)?)
+        })
+        .context(|| format!("unable to set the mtu of {}", link.name))?;
+        if mtu != link.mtu {
+            warn!(
+                "mtu not applied: interface={} requested={} applied={}",
+                link.name, link.mtu, mtu
+            );
+        }
+
+        for offload in &link.disable {
+            info!(
+                "disabling offload: interface={} offload={}",
+                link.name, offload
+            );
+            let active = caps::with(Capability::CAP_NET_ADMIN, || -> Result<bool> {
+                ipvlan.set_offload(*offload, false)?;
+                Ok(ipvlan.offload(*offload)?)
+            })
+            .context(|| format!("unable to disable {} on {}", offload, link.name))?;
+            if active {
+                warn!(
+                    "offload not disabled: interface={} offload={}",
+                    link.name, offload
+                );
+            }
+        }
+
+        if let Some(rate) = link.rate {
+            // Allow bursts of 10ms at the rate, but at least two packets.
+            let qdisc = Qdisc::Tbf {
+                rate,
+                burst: (rate / 800).max(u64::from(mtu) * 2).min(u32::MAX.into()) as u32,
+                latency: Duration::from_millis(50),
+            };
+
+            info!("setting qdisc: interface={} qdisc={}", link.name, qdisc);
+            caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+                Ok(ipvlan.set_qdisc(&qdisc)?)
+            })
+            .context(|| format!("unable to limit the rate of {}", link.name))?;
+        }
+
+        for (address, gateway) in &link.addresses {
+            let subnet = gateway.subnet();
+            let lifetime = config.subnets[&subnet].lifetime;
+            info!(
+                "assigning address: interface={} address={}/{} gateway={} lifetime={:?}",
+                link.name,
+                address,
+                subnet.prefix(),
+                gateway.address(),
+                lifetime
+            );
+            let options = AddressOptions {
+                preferred: lifetime,
+                valid: lifetime,
+                ..Default::default()
+            };
+            caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+                ipvlan
+                    .add_address_with(*address, subnet.prefix(), options)
+                    .context(|| format!("unable to assign {} to {}", address, link.name))?;
+                ipvlan
+                    .up()
+                    .context(|| format!("unable to bring up {}", link.name))
+            })?
+        }
+
+        for route in &link.defaults {
+            info!(
+                "adding default route: interface={} route={} metric={:?}",
+                link.name,
+                route,
+                route.metric()
+            );
+            caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+                Ok(route.add(&ipvlan)?)
+            })
+            .context(|| format!("unable to add default route {} on {}", route, link.name))?
+        }
+
+        for route in &link.routes {
+            info!("adding route: interface={} route={}", link.name, route);
+            caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+                Ok(route.add(&ipvlan)?)
+            })
+            .context(|| format!("unable to add route {} on {}", route, link.name))?
+        }
+    }
+
+    // Bring up the loopback interface.
+    if !options.no_loopback {
+        info!("bringing up loopback");
+        let mut ipvlan = Interface::find("lo")?;
+        caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+            let host = AddressOptions {
+                scope: netlink::RT_SCOPE_HOST,
+                ..Default::default()
+            };
+            ipvlan.add_address_with(IpAddr::V6(LO_ADDR6.into()), 128, host)?;
+            ipvlan.add_address_with(IpAddr::V4(LO_ADDR4.into()), 8, host)?;
+            for (address, prefix) in &config.loopback {
+                debug!("assigning loopback address: address={}/{}", address, prefix);
+                // The configuration may repeat the standard addresses.
+                match ipvlan.add_address(*address, *prefix) {
+                    Err(e) if e.exists() => debug!("loopback address exists: address={}", address),
+                    Err(e) => return Err(e.into()),
+                    Ok(..) => (),
+                }
+            }
+            ipvlan.up()?;
+            Ok(())
+        })
+        .context(|| "unable to set up loopback".into())?;
+    }
+
+    // Create the dummy interface for the extra addresses.
+    if !config.dummy.is_empty() {
+        info!("creating dummy: name={}", DUMMY);
+        caps::with(Capability::CAP_NET_ADMIN, || -> Result<()> {
+            let mut dummy = Interface::add_dummy(DUMMY)?;
+            for (address, prefix) in &config.dummy {
+                debug!("assigning dummy address: address={}/{}", address, prefix);
+                dummy.add_address(*address, *prefix)?;
+            }
+            dummy.up()?;
+            Ok(())
+        })
+        .context(|| format!("unable to set up {}", DUMMY))?;
+    }
+
+    // Apply the network sysctl settings.
+    for sysctl in &config.sysctl {
+        info!("setting sysctl: sysctl={}", sysctl);
+        caps::with(Capability::CAP_NET_ADMIN, || sysctl.apply())
+            .context(|| format!("unable to set {}", sysctl.key()))?;
+    }
+
+    // Load the firewall before anything runs in the namespace.
+    if let Some(ruleset) = &ruleset {
+        info!("loading nftables ruleset");
+        nft::load(ruleset).context(|| "unable to load the nftables ruleset".into())?;
+    }
+
+    caps::drop(None, CapSet::Permitted, Capability::CAP_NET_ADMIN)?;
+
+    // Release the lock.
+    drop(conf);
+    drop(lock);
+
+    if options.print_addrs {
+        print_addrs(&record.links, options.json)?;
+    }
+
+    if daemon {
+        return hold(listeners, record, &options);
+    }
+
+    let mut command = command(&program, &argv, &options, &record.links)?;
+    if options.supervise {
+        let timeout = options.timeout.map(Duration::from_secs);
+        let pid_file = options.pid_file.as_deref();
+        return supervise(command, identity, listeners, &record, timeout, pid_file);
+    }
+
+    if let Some(path) = &options.pid_file {
+        write_pid_file(path, std::process::id(), &record)?;
+    }
+
+    // Switch to the requested user.
+    if let Some(identity) = &identity {
+        info!(
+            "switching user: uid={} gid={} groups={:?}",
+            identity.uid, identity.gid, identity.groups
+        );
+        identity.apply()?;
+        caps::clear(None, CapSet::Permitted)?;
+    }
+
+    info!("executing: argv={:?}", argv);
+    Err(command.exec())
 }
@@ -2,17 +2,90 @@
 
 use super::{Connection, Error, Interface, Subnet};
 
+use log::debug;
 use netlink_packet_route::*;
 
-use std::convert::TryFrom;
 use std::io::ErrorKind;
 use std::net::IpAddr;
+use std::time::Duration;
+
+pub use netlink_packet_route::{IFA_F_NODAD, IFA_F_NOPREFIXROUTE, IFA_F_PERMANENT};
+pub use netlink_packet_route::{RT_SCOPE_HOST, RT_SCOPE_LINK, RT_SCOPE_UNIVERSE};
+
+/// How to add an address (see `Interface::add_address_with()`)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressOptions {
+    /// The address flags (`IFA_F_*`), such as `IFA_F_NODAD`
+    pub flags: u32,
+
+    /// The scope (`RT_SCOPE_*`) of the address, such as `RT_SCOPE_HOST`
+    pub scope: u8,
+
+    /// How long the address is preferred for new connections (or forever)
+    pub preferred: Option<Duration>,
+
+    /// How long until the kernel removes the address (or forever)
+    pub valid: Option<Duration>,
+}
+
+/// The lifetime the kernel treats as infinite
+const INFINITY_LIFE_TIME: u32 = u32::MAX;
+
+/// Encodes a lifetime in seconds for `IFA_CACHEINFO`
+fn seconds(lifetime: Option<Duration>) -> u32 {
+    match lifetime {
+        Some(lifetime) => lifetime.as_secs().min(u64::from(INFINITY_LIFE_TIME - 1)) as u32,
+        None => INFINITY_LIFE_TIME,
+    }
+}
+
+impl AddressOptions {
+    /// Returns the `IFA_CACHEINFO` payload, if any lifetime is finite
+    pub(super) fn cache_info(&self) -> Option<Vec<u8>> {
+        if self.preferred.is_none() && self.valid.is_none() {
+            return None;
+        }
+
+        // struct ifa_cacheinfo: preferred, valid, created, updated
+        let mut info = Vec::with_capacity(16);
+        info.extend_from_slice(&seconds(self.preferred).to_ne_bytes());
+        info.extend_from_slice(&seconds(self.valid).to_ne_bytes());
+        info.extend_from_slice(&[0u8; 8]);
+        Some(info)
+    }
+}
+
+/// Which addresses to list (see `Address::list_filtered()`)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressFilter {
+    /// Only addresses of this family (`AF_INET` or `AF_INET6`)
+    pub family: Option<u16>,
+
+    /// Only addresses on the interface with this index
+    pub index: Option<u32>,
+}
+
+impl AddressFilter {
+    #[inline]
+    fn matches(&self, address: &Address) -> bool {
+        let family = match address.address {
+            IpAddr::V4(..) => AF_INET,
+            IpAddr::V6(..) => AF_INET6,
+        };
+
+        self.family.map_or(true, |f| f == family) && self.index.map_or(true, |i| i == address.index)
+    }
+}
 
 #[derive(Copy, Clone, Debug, Hash)]
 pub struct Address {
     index: u32,
     subnet: Subnet,
     address: IpAddr,
+    flags: u32,
+    scope: u8,
+    preferred: Option<Duration>,
+    valid: Option<Duration>,
 }
 
 impl Address {
@@ -22,19 +95,139 @@ impl Address {
             index,
             address,
             subnet: Subnet::new(address, prefix),
+            flags: 0,
+            scope: RT_SCOPE_UNIVERSE,
+            preferred: None,
+            valid: None,
         }
     }
 
+    /// Sets the flags of the address
+    #[inline]
+    pub(super) fn with_flags(self, flags: u32) -> Self {
+        Self { flags, ..self }
+    }
+
+    /// Sets the scope of the address
+    #[inline]
+    pub(super) fn with_scope(self, scope: u8) -> Self {
+        Self { scope, ..self }
+    }
+
+    /// Sets the remaining lifetimes of the address
+    #[inline]
+    pub(super) fn with_lifetimes(
+        self,
+        preferred: Option<Duration>,
+        valid: Option<Duration>,
+    ) -> Self {
+        Self {
+            preferred,
+            valid,
+            ..self
+        }
+    }
+
+    /// Decodes the address carried by a message (if any)
+    pub(super) fn decode(msg: &AddressMessage) -> Option<Self> {
+        // The attribute carries all flags; the header only the first eight.
+        let flags = msg
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                address::Nla::Flags(flags) => Some(*flags),
+                _ => None,
+            })
+            .unwrap_or_else(|| msg.header.flags.into());
+
+        let lifetime = |bytes: &[u8]| {
+            let mut secs = [0u8; 4];
+            secs.copy_from_slice(bytes);
+            match u32::from_ne_bytes(secs) {
+                INFINITY_LIFE_TIME => None,
+                secs => Some(Duration::from_secs(secs.into())),
+            }
+        };
+
+        let (preferred, valid) = msg
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                address::Nla::CacheInfo(info) if info.len() >= 8 => {
+                    Some((lifetime(&info[..4]), lifetime(&info[4..8])))
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        for nla in &msg.nlas {
+            let address = match nla {
+                address::Nla::Address(addr) => match (msg.header.family.into(), addr.len()) {
+                    (AF_INET, 4) => {
+                        let mut bytes = [0u8; 4];
+                        bytes.copy_from_slice(addr);
+                        IpAddr::V4(bytes.into())
+                    }
+
+                    (AF_INET6, 16) => {
+                        let mut bytes = [0u8; 16];
+                        bytes.copy_from_slice(addr);
+                        IpAddr::V6(bytes.into())
+                    }
+
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            let address = Address::new(msg.header.index, address, msg.header.prefix_len);
+            let address = address
+                .with_flags(flags)
+                .with_scope(msg.header.scope)
+                .with_lifetimes(preferred, valid);
+            return Some(address);
+        }
+
+        None
+    }
+
     #[inline]
     pub fn list() -> Result<Vec<Self>, Error> {
-        let mut nl = Connection::new()?;
+        Self::list_in(&mut Connection::new()?)
+    }
 
+    /// Lists the addresses in the namespace of the connection
+    #[inline]
+    pub fn list_in(nl: &mut Connection) -> Result<Vec<Self>, Error> {
+        Self::list_filtered(nl, AddressFilter::default())
+    }
+
+    /// Lists the addresses matching `filter` in the namespace of the
+    /// connection
+    ///
+    /// The kernel does the filtering where it can: always by family, and
+    /// by interface when strict checking is enabled. Whatever it passes
+    /// through is filtered here.
+    pub fn list_filtered(nl: &mut Connection, filter: AddressFilter) -> Result<Vec<Self>, Error> {
+        debug!(
+            "listing addresses: filter={:?} strict={}",
+            filter,
+            nl.strict()
+        );
         nl.push(NetlinkMessage {
             header: NetlinkHeader {
                 flags: NLM_F_REQUEST | NLM_F_DUMP,
                 ..Default::default()
             },
-            payload: RtnlMessage::GetAddress(Default::default()).into(),
+            payload: RtnlMessage::GetAddress(AddressMessage {
+                header: AddressHeader {
+                    family: filter.family.unwrap_or(AF_UNSPEC) as u8,
+                    index: filter.index.unwrap_or(0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .into(),
         })?;
 
         let mut addresses = Vec::new();
@@ -43,40 +236,8 @@ impl Address {
                 NetlinkPayload::Done => break Ok(addresses),
 
                 NetlinkPayload::InnerMessage(RtnlMessage::NewAddress(msg)) => {
-                    for nla in msg.nlas {
-                        let (address, subnet) = match nla {
-                            address::Nla::Address(addr) => match msg.header.family.into() {
-                                AF_INET => {
-                                    let mut bytes = [0u8; 4];
-                                    bytes.copy_from_slice(&addr);
-
-                                    (
-                                        IpAddr::V4(bytes.into()),
-                                        Subnet::new(bytes.into(), msg.header.prefix_len),
-                                    )
-                                }
-
-                                AF_INET6 => {
-                                    let mut bytes = [0u8; 16];
-                                    bytes.copy_from_slice(&addr);
-
-                                    (
-                                        IpAddr::V6(bytes.into()),
-                                        Subnet::new(bytes.into(), msg.header.prefix_len),
-                                    )
-                                }
-
-                                _ => continue,
-                            },
-                            _ => continue,
-                        };
-
-                        addresses.push(Address {
-                            index: msg.header.index,
-                            subnet,
-                            address,
-                        })
-                    }
+                    let decoded = Self::decode(&msg).filter(|a| filter.matches(a));
+                    addresses.extend(decoded);
                 }
 
                 _ => return Err(ErrorKind::InvalidData.into()),
@@ -84,6 +245,78 @@ impl Address {
         }
     }
 
+    /// Removes the address from its interface
+    #[allow(dead_code)]
+    pub fn delete(&self) -> Result<(), Error> {
+        debug!(
+            "deleting address: index={} address={}/{}",
+            self.index,
+            self.address,
+            self.subnet.prefix()
+        );
+        let bytes: Vec<u8> = match self.address {
+            IpAddr::V4(x) => x.octets().into(),
+            IpAddr::V6(x) => x.octets().into(),
+        };
+
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK,
+                ..Default::default()
+            },
+            payload: RtnlMessage::DelAddress(AddressMessage {
+                header: AddressHeader {
+                    index: self.index,
+                    prefix_len: self.subnet.prefix(),
+                    family: match self.address {
+                        IpAddr::V4(..) => AF_INET as _,
+                        IpAddr::V6(..) => AF_INET6 as _,
+                    },
+                    ..Default::default()
+                },
+                nlas: vec![
+                    address::Nla::Address(bytes.clone()),
+                    address::Nla::Local(bytes),
+                ],
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(()),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Returns the address flags (`IFA_F_*`)
+    #[inline]
+    #[allow(dead_code)]
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Returns the scope (`RT_SCOPE_*`) of the address
+    #[inline]
+    #[allow(dead_code)]
+    pub fn scope(&self) -> u8 {
+        self.scope
+    }
+
+    /// Returns how long the address remains preferred (`None` if forever)
+    #[inline]
+    #[allow(dead_code)]
+    pub fn preferred(&self) -> Option<Duration> {
+        self.preferred
+    }
+
+    /// Returns how long the address remains valid (`None` if forever)
+    #[inline]
+    #[allow(dead_code)]
+    pub fn valid(&self) -> Option<Duration> {
+        self.valid
+    }
+
     #[inline]
     pub fn subnet(&self) -> Subnet {
         self.subnet
@@ -96,23 +329,41 @@ impl Address {
 
     #[inline]
     pub fn interface(&self) -> Result<Interface, Error> {
-        let mut nl = Connection::new()?;
+        Interface::from_index(self.index)
+    }
+}
 
-        nl.push(NetlinkMessage {
-            header: NetlinkHeader {
-                flags: NLM_F_REQUEST,
-                ..Default::default()
-            },
-            payload: RtnlMessage::GetLink(LinkMessage {
-                header: LinkHeader {
-                    index: self.index,
-                    ..Default::default()
-                },
-                ..Default::default()
-            })
-            .into(),
-        })?;
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Splits a `struct ifa_cacheinfo` into its fields
+    fn fields(info: &[u8]) -> Vec<u32> {
+        info.chunks(4)
+            .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn cache_info_forever() {
+        assert_eq!(AddressOptions::default().cache_info(), None);
+    }
+
+    #[test]
+    fn cache_info() {
+        let options = AddressOptions {
+            valid: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let info = options.cache_info().unwrap();
+        assert_eq!(fields(&info), [INFINITY_LIFE_TIME, 3600, 0, 0]);
 
-        Ok(Interface::try_from(nl.pull()?.payload)?)
+        let options = AddressOptions {
+            preferred: Some(Duration::from_secs(60)),
+            valid: Some(Duration::from_secs(u64::MAX)),
+            ..Default::default()
+        };
+        let info = options.cache_info().unwrap();
+        assert_eq!(fields(&info), [60, INFINITY_LIFE_TIME - 1, 0, 0]);
     }
 }
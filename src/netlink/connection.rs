@@ -1,12 +1,194 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::Error;
+use super::{Error, Reason};
 
-use netlink_packet_core::{NetlinkDeserializable, NetlinkSerializable};
-use netlink_packet_route::NetlinkMessage;
-use netlink_sys::protocols::NETLINK_ROUTE;
+use netlink_packet_core::{ErrorMessage, NetlinkDeserializable, NetlinkSerializable};
+use netlink_packet_route::{NetlinkMessage, NetlinkPayload};
+use netlink_sys::protocols::{NETLINK_GENERIC, NETLINK_ROUTE};
 use netlink_sys::{Socket, SocketAddr};
 
+use log::{debug, trace};
+
+use std::os::unix::io::AsRawFd;
+
+/// The request echoed in the error was truncated to its header
+const NLM_F_CAPPED: u16 = 0x100;
+
+/// Extended acknowledgement attributes follow the echoed request
+const NLM_F_ACK_TLVS: u16 = 0x200;
+
+/// Makes the kernel validate requests strictly and honor dump filters
+/// (since Linux 4.20)
+const NETLINK_GET_STRICT_CHK: libc::c_int = 12;
+
+const NLMSGERR_ATTR_MSG: u16 = 1;
+const NLMSGERR_ATTR_OFFS: u16 = 2;
+
+/// Rounds `len` up to the netlink alignment
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Converts an error message into an error, decoding the extended
+/// acknowledgement (if any)
+fn kernel(msg: &ErrorMessage, flags: u16) -> Error {
+    let reason = Reason::from(-msg.code);
+    let error = std::io::Error::from_raw_os_error(-msg.code);
+    let mut message = None;
+    let mut offset = None;
+
+    if flags & NLM_F_ACK_TLVS != 0 && msg.header.len() >= 16 {
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&msg.header[..4]);
+        let echoed = match flags & NLM_F_CAPPED {
+            0 => u32::from_ne_bytes(len) as usize,
+            _ => 16,
+        };
+
+        let mut attrs = msg.header.get(align(echoed)..).unwrap_or_default();
+        while attrs.len() >= 4 {
+            let len = usize::from(u16::from_ne_bytes([attrs[0], attrs[1]]));
+            let kind = u16::from_ne_bytes([attrs[2], attrs[3]]);
+            if len < 4 || len > attrs.len() {
+                break;
+            }
+
+            let value = &attrs[4..len];
+            match kind {
+                NLMSGERR_ATTR_MSG => {
+                    let text = String::from_utf8_lossy(value);
+                    message = Some(text.trim_end_matches('\0').to_string());
+                }
+
+                NLMSGERR_ATTR_OFFS if value.len() == 4 => {
+                    offset = Some(u32::from_ne_bytes([value[0], value[1], value[2], value[3]]));
+                }
+
+                _ => (),
+            }
+
+            attrs = &attrs[align(len).min(attrs.len())..];
+        }
+    }
+
+    Error::Kernel {
+        reason,
+        error,
+        message,
+        offset,
+    }
+}
+
+/// Sets a netlink socket option
+fn setsockopt(socket: &Socket, option: libc::c_int, value: libc::c_int) -> std::io::Result<()> {
+    match unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_NETLINK,
+            option,
+            &value as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } {
+        -1 => Err(std::io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unreachable!(),
+    }
+}
+
+/// Opens a socket connected to the kernel for `protocol` (`NETLINK_*`)
+pub(super) fn socket(protocol: isize) -> std::io::Result<Socket> {
+    let socket = Socket::new(protocol)?;
+    socket.connect(&SocketAddr::new(0, 0))?;
+
+    // Ask the kernel to explain errors. Older kernels don't support it.
+    if let Err(e) = setsockopt(&socket, libc::NETLINK_EXT_ACK, 1) {
+        debug!("extended acks unsupported: {}", e);
+    }
+
+    // Ask the kernel to check requests strictly. Older kernels ignore the
+    // filters in dump requests instead, so callers must filter the results
+    // again themselves (see `Connection::strict()`).
+    if let Err(e) = setsockopt(&socket, NETLINK_GET_STRICT_CHK, 1) {
+        debug!("strict checking unsupported: {}", e);
+    }
+
+    Ok(socket)
+}
+
+/// Numbers and serializes a message
+pub(super) fn encode<I>(sequence: &mut u32, mut msg: NetlinkMessage<I>) -> Vec<u8>
+where
+    I: std::fmt::Debug + PartialEq<I> + Eq + Clone + NetlinkSerializable<I>,
+{
+    *sequence += 1;
+    msg.header.sequence_number = *sequence;
+    msg.finalize();
+    trace!("netlink send: {:?}", msg);
+
+    let mut buffer = vec![0u8; msg.buffer_len()];
+    msg.serialize(&mut buffer);
+    buffer
+}
+
+/// Receives the next datagram into `buffer`, growing it as needed
+///
+/// The kernel truncates datagrams which don't fit, so the size of each one
+/// is peeked at first. If the socket overflowed (`ENOBUFS`), messages were
+/// lost and the error is returned; a dump has to be started over.
+pub(super) fn receive(socket: &Socket, buffer: &mut Vec<u8>) -> std::io::Result<usize> {
+    let len = socket.recv(&mut buffer[..], libc::MSG_PEEK | libc::MSG_TRUNC)?;
+    if len > buffer.len() {
+        debug!("growing receive buffer: from={} to={}", buffer.len(), len);
+        buffer.resize(len, 0);
+    }
+
+    socket.recv(&mut buffer[..], 0)
+}
+
+/// Deserializes the message at `first` in `buffer`, advancing past it
+///
+/// Once requests have been sent, replies to requests other than the last
+/// one (`sequence`) are stale, for example the rest of an abandoned dump,
+/// and are skipped (`None`). Connections which only receive notifications
+/// never send requests, so they see every message.
+pub(super) fn decode<I>(
+    buffer: &[u8],
+    first: &mut usize,
+    sequence: u32,
+) -> Result<Option<NetlinkMessage<I>>, Error>
+where
+    I: std::fmt::Debug + PartialEq<I> + Eq + Clone + NetlinkDeserializable<I>,
+{
+    let msg = NetlinkMessage::<I>::deserialize(&buffer[*first..])?;
+    let len = msg.header.length as usize;
+    if len == 0 || *first + len > buffer.len() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("truncated netlink message: length={}", len),
+        )
+        .into());
+    }
+
+    *first += len;
+    trace!("netlink recv: {:?}", msg);
+
+    let number = msg.header.sequence_number;
+    if sequence != 0 && number != 0 && number != sequence {
+        debug!(
+            "skipping stale message: sequence={} expected={}",
+            number, sequence
+        );
+        return Ok(None);
+    }
+
+    if let NetlinkPayload::Error(e) = &msg.payload {
+        return Err(kernel(e, msg.header.flags));
+    }
+
+    Ok(Some(msg))
+}
+
 pub struct Connection {
     socket: netlink_sys::Socket,
     buffer: Vec<u8>,
@@ -15,31 +197,98 @@ pub struct Connection {
     sequence: u32,
 }
 
-impl Connection {
-    pub fn new() -> std::io::Result<Self> {
-        let socket = Socket::new(NETLINK_ROUTE)?;
-        socket.connect(&SocketAddr::new(0, 0))?;
-
-        Ok(Self {
+impl From<Socket> for Connection {
+    fn from(socket: Socket) -> Self {
+        Self {
             socket,
             buffer: vec![0u8; 4096],
             first: 0,
             last: 0,
             sequence: 0,
-        })
+        }
+    }
+}
+
+impl Connection {
+    /// Opens an rtnetlink connection
+    #[inline]
+    pub fn new() -> std::io::Result<Self> {
+        Self::open(NETLINK_ROUTE)
+    }
+
+    /// Opens a generic netlink connection (see `Connection::family()`)
+    #[inline]
+    pub fn generic() -> std::io::Result<Self> {
+        Self::open(NETLINK_GENERIC)
+    }
+
+    /// Opens an rtnetlink connection within the network namespace `nsfd`
+    ///
+    /// The socket is created by a helper thread which enters the namespace,
+    /// so the caller's namespace never changes. The socket stays bound to
+    /// the namespace it was created in. The helper inherits the calling
+    /// thread's capabilities, so `CAP_SYS_ADMIN` must be effective.
+    pub fn in_namespace(nsfd: &impl AsRawFd) -> std::io::Result<Self> {
+        let fd = nsfd.as_raw_fd();
+        debug!("opening connection in namespace: nsfd={}", fd);
+
+        // The thread is joined before `nsfd` can be closed.
+        let helper = std::thread::spawn(move || -> std::io::Result<Socket> {
+            match unsafe { libc::setns(fd, libc::CLONE_NEWNET) } {
+                -1 => Err(std::io::Error::last_os_error()),
+                0 => socket(NETLINK_ROUTE),
+                _ => unreachable!(),
+            }
+        });
+
+        match helper.join() {
+            Ok(socket) => Ok(Self::from(socket?)),
+            Err(..) => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "namespace helper panicked",
+            )),
+        }
+    }
+
+    #[inline]
+    fn open(protocol: isize) -> std::io::Result<Self> {
+        Ok(Self::from(socket(protocol)?))
+    }
+
+    /// Whether the kernel checks requests strictly, honoring dump filters
+    pub fn strict(&self) -> bool {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+        match unsafe {
+            libc::getsockopt(
+                self.socket.as_raw_fd(),
+                libc::SOL_NETLINK,
+                NETLINK_GET_STRICT_CHK,
+                &mut value as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        } {
+            -1 => false,
+            0 => value != 0,
+            _ => unreachable!(),
+        }
     }
 
-    pub fn push<I>(&mut self, mut msg: NetlinkMessage<I>) -> std::io::Result<usize>
+    /// Subscribes to the multicast `group` (`RTNLGRP_*`)
+    pub fn subscribe(&mut self, group: u32) -> std::io::Result<()> {
+        setsockopt(
+            &self.socket,
+            libc::NETLINK_ADD_MEMBERSHIP,
+            group as libc::c_int,
+        )
+    }
+
+    pub fn push<I>(&mut self, msg: NetlinkMessage<I>) -> std::io::Result<usize>
     where
         I: std::fmt::Debug + PartialEq<I> + Eq + Clone + NetlinkSerializable<I>,
     {
-        self.sequence += 1;
-        msg.header.sequence_number = self.sequence;
-        msg.finalize();
-
-        let mut buffer = vec![0u8; msg.buffer_len()];
-        msg.serialize(&mut buffer);
-
+        let buffer = encode(&mut self.sequence, msg);
         self.socket.send(&buffer, 0)
     }
 
@@ -47,13 +296,89 @@ impl Connection {
     where
         I: std::fmt::Debug + PartialEq<I> + Eq + Clone + NetlinkDeserializable<I>,
     {
-        if self.first == self.last {
-            self.last = self.socket.recv(&mut self.buffer[..], 0)?;
-            self.first = 0;
+        loop {
+            if self.first == self.last {
+                self.last = receive(&self.socket, &mut self.buffer)?;
+                self.first = 0;
+            }
+
+            let buffer = &self.buffer[..self.last];
+            if let Some(msg) = decode(buffer, &mut self.first, self.sequence)? {
+                return Ok(msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::generic::{encode, Attribute};
+    use super::*;
+
+    /// Returns an error echoing `echoed` bytes of a request of `len` bytes,
+    /// followed by `attributes`
+    fn error(len: u32, echoed: usize, attributes: &[Attribute]) -> ErrorMessage {
+        let mut header = vec![0u8; echoed];
+        header[..4].copy_from_slice(&len.to_ne_bytes());
+        header.extend(encode(attributes));
+
+        ErrorMessage {
+            code: -libc::EINVAL,
+            header,
         }
+    }
+
+    /// Returns the explanation and offset decoded from an error
+    fn decoded(msg: &ErrorMessage, flags: u16) -> (Option<String>, Option<u32>) {
+        match kernel(msg, flags) {
+            Error::Kernel {
+                reason,
+                message,
+                offset,
+                ..
+            } => {
+                assert_eq!(reason, Reason::InvalidArgument);
+                (message, offset)
+            }
+
+            error => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn kernel_capped() {
+        let attributes = [
+            Attribute::string(NLMSGERR_ATTR_MSG, "Invalid prefix length"),
+            Attribute::new(NLMSGERR_ATTR_OFFS, 24u32.to_ne_bytes().to_vec()),
+        ];
+        let msg = error(40, 16, &attributes);
+
+        let (message, offset) = decoded(&msg, NLM_F_ACK_TLVS | NLM_F_CAPPED);
+        assert_eq!(message.as_deref(), Some("Invalid prefix length"));
+        assert_eq!(offset, Some(24));
+    }
+
+    #[test]
+    fn kernel_uncapped() {
+        // The whole request is echoed, padded to the netlink alignment.
+        let attributes = [Attribute::string(NLMSGERR_ATTR_MSG, "Unknown device")];
+        let msg = error(22, 24, &attributes);
+
+        let (message, offset) = decoded(&msg, NLM_F_ACK_TLVS);
+        assert_eq!(message.as_deref(), Some("Unknown device"));
+        assert_eq!(offset, None);
+    }
+
+    #[test]
+    fn kernel_plain() {
+        let attributes = [Attribute::string(NLMSGERR_ATTR_MSG, "ignored")];
+        let msg = error(40, 16, &attributes);
+        assert_eq!(decoded(&msg, 0), (None, None));
 
-        let msg = NetlinkMessage::<I>::deserialize(&self.buffer[self.first..self.last])?;
-        self.first += msg.header.length as usize;
-        Ok(msg)
+        let error = kernel(&msg, 0);
+        assert_eq!(
+            error.to_string(),
+            std::io::Error::from_raw_os_error(libc::EINVAL).to_string()
+        );
     }
 }
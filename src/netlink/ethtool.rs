@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Offload control through the ethtool generic netlink family
+//!
+//! The family appeared in Linux 5.6; older kernels fail to resolve it.
+
+use super::generic::{Attribute, GenericMessage};
+use super::{Connection, Error, Interface};
+
+use log::debug;
+use netlink_packet_route::{DecodeError, NetlinkHeader, NetlinkMessage, NetlinkPayload};
+use netlink_packet_route::{NLM_F_ACK, NLM_F_REQUEST};
+
+use std::io::ErrorKind;
+use std::str::FromStr;
+
+const ETHTOOL_GENL_NAME: &str = "ethtool";
+const ETHTOOL_GENL_VERSION: u8 = 1;
+
+const ETHTOOL_MSG_FEATURES_GET: u8 = 11;
+const ETHTOOL_MSG_FEATURES_SET: u8 = 12;
+
+const ETHTOOL_A_HEADER_DEV_INDEX: u16 = 1;
+const ETHTOOL_A_HEADER_FLAGS: u16 = 3;
+const ETHTOOL_FLAG_OMIT_REPLY: u32 = 1 << 1;
+
+const ETHTOOL_A_FEATURES_HEADER: u16 = 1;
+const ETHTOOL_A_FEATURES_WANTED: u16 = 3;
+const ETHTOOL_A_FEATURES_ACTIVE: u16 = 4;
+
+const ETHTOOL_A_BITSET_NOMASK: u16 = 1;
+const ETHTOOL_A_BITSET_BITS: u16 = 3;
+const ETHTOOL_A_BITSETS_BIT: u16 = 1;
+const ETHTOOL_A_BITSET_BIT_NAME: u16 = 2;
+const ETHTOOL_A_BITSET_BIT_VALUE: u16 = 3;
+
+/// An offload which can be toggled on an interface
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Offload {
+    /// Generic receive offload
+    Gro,
+
+    /// Generic segmentation offload
+    Gso,
+
+    /// TCP segmentation offload (for both IPv4 and IPv6)
+    Tso,
+
+    /// Receive checksum offload
+    RxChecksum,
+}
+
+impl std::fmt::Display for Offload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Gro => write!(f, "gro"),
+            Self::Gso => write!(f, "gso"),
+            Self::Tso => write!(f, "tso"),
+            Self::RxChecksum => write!(f, "rx-checksum"),
+        }
+    }
+}
+
+impl FromStr for Offload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gro" => Ok(Self::Gro),
+            "gso" => Ok(Self::Gso),
+            "tso" => Ok(Self::Tso),
+            "rx-checksum" => Ok(Self::RxChecksum),
+            _ => Err(format!("invalid offload: {}", s)),
+        }
+    }
+}
+
+impl Offload {
+    /// Returns the names of the kernel features making up the offload
+    fn features(self) -> &'static [&'static str] {
+        match self {
+            Self::Gro => &["rx-gro"],
+            Self::Gso => &["tx-generic-segmentation"],
+            Self::Tso => &["tx-tcp-segmentation", "tx-tcp6-segmentation"],
+            Self::RxChecksum => &["rx-checksum"],
+        }
+    }
+}
+
+/// Returns the request header naming the interface
+fn header(index: u32, flags: u32) -> Attribute {
+    Attribute::nested(
+        ETHTOOL_A_FEATURES_HEADER,
+        &[
+            Attribute::new(ETHTOOL_A_HEADER_DEV_INDEX, index.to_ne_bytes().to_vec()),
+            Attribute::new(ETHTOOL_A_HEADER_FLAGS, flags.to_ne_bytes().to_vec()),
+        ],
+    )
+}
+
+/// Returns the names of the bits set in `bitset`
+///
+/// Without compact bitsets, each bit is listed by name. A list (flagged
+/// with nomask) holds only the set bits; otherwise, set bits carry a value
+/// flag and the others are listed without one.
+fn names(bitset: &Attribute) -> Result<Vec<String>, DecodeError> {
+    let attributes = bitset.attributes()?;
+    let list = attributes
+        .iter()
+        .any(|a| a.kind() == ETHTOOL_A_BITSET_NOMASK);
+
+    let mut names = Vec::new();
+    for bits in attributes
+        .iter()
+        .filter(|a| a.kind() == ETHTOOL_A_BITSET_BITS)
+    {
+        for bit in bits.attributes()? {
+            if bit.kind() != ETHTOOL_A_BITSETS_BIT {
+                continue;
+            }
+
+            let fields = bit.attributes()?;
+            let on = list
+                || fields
+                    .iter()
+                    .any(|f| f.kind() == ETHTOOL_A_BITSET_BIT_VALUE);
+            let name = fields
+                .iter()
+                .find(|f| f.kind() == ETHTOOL_A_BITSET_BIT_NAME)
+                .and_then(Attribute::as_str);
+
+            if let Some(name) = name.filter(|_| on) {
+                names.push(name.to_string());
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+/// Returns the active features listed in a features reply
+fn active(attributes: &[Attribute]) -> Result<Vec<String>, DecodeError> {
+    let mut active = Vec::new();
+    for attribute in attributes {
+        if attribute.kind() == ETHTOOL_A_FEATURES_ACTIVE {
+            active.extend(names(attribute)?);
+        }
+    }
+
+    Ok(active)
+}
+
+impl Interface {
+    /// Lists the names of the active kernel features (e.g. `rx-gro`)
+    pub fn features(&self) -> Result<Vec<String>, Error> {
+        debug!("reading features: interface={}", self);
+        let mut nl = Connection::generic()?;
+        let family = nl.family(ETHTOOL_GENL_NAME)?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST,
+                ..Default::default()
+            },
+            payload: GenericMessage {
+                family,
+                command: ETHTOOL_MSG_FEATURES_GET,
+                version: ETHTOOL_GENL_VERSION,
+                attributes: vec![header(self.index(), 0)],
+            }
+            .into(),
+        })?;
+
+        let msg = match nl.pull::<GenericMessage>()?.payload {
+            NetlinkPayload::InnerMessage(msg) => msg,
+            _ => return Err(ErrorKind::InvalidData.into()),
+        };
+
+        Ok(active(&msg.attributes)?)
+    }
+
+    /// Whether all the features making up `offload` are active
+    pub fn offload(&self, offload: Offload) -> Result<bool, Error> {
+        let active = self.features()?;
+        Ok(offload
+            .features()
+            .iter()
+            .all(|f| active.iter().any(|a| a == f)))
+    }
+
+    /// Turns `offload` on or off
+    ///
+    /// The kernel may refuse to change features the driver fixes, in which
+    /// case they are left as they were without an error; check the result
+    /// with `offload()`.
+    pub fn set_offload(&mut self, offload: Offload, enabled: bool) -> Result<(), Error> {
+        debug!(
+            "setting offload: interface={} offload={} enabled={}",
+            self, offload, enabled
+        );
+
+        // Listing a bit without a value flag turns it off.
+        let bits: Vec<Attribute> = offload
+            .features()
+            .iter()
+            .map(|name| {
+                let mut fields = vec![Attribute::string(ETHTOOL_A_BITSET_BIT_NAME, name)];
+                if enabled {
+                    fields.push(Attribute::new(ETHTOOL_A_BITSET_BIT_VALUE, Vec::new()));
+                }
+                Attribute::nested(ETHTOOL_A_BITSETS_BIT, &fields)
+            })
+            .collect();
+
+        let mut nl = Connection::generic()?;
+        let family = nl.family(ETHTOOL_GENL_NAME)?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK,
+                ..Default::default()
+            },
+            payload: GenericMessage {
+                family,
+                command: ETHTOOL_MSG_FEATURES_SET,
+                version: ETHTOOL_GENL_VERSION,
+                attributes: vec![
+                    header(self.index(), ETHTOOL_FLAG_OMIT_REPLY),
+                    Attribute::nested(
+                        ETHTOOL_A_FEATURES_WANTED,
+                        &[Attribute::nested(ETHTOOL_A_BITSET_BITS, &bits)],
+                    ),
+                ],
+            }
+            .into(),
+        })?;
+
+        match nl.pull::<GenericMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(()),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::generic::decode;
+    use super::*;
+
+    /// The attributes of a features reply for a veth, trimmed to a few bits
+    #[cfg(target_endian = "little")]
+    const REPLY: &[u8] = &[
+        0x18, 0x00, 0x01, 0x80, 0x08, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x09, 0x00, 0x02,
+        0x00, 0x65, 0x74, 0x68, 0x30, 0x00, 0x00, 0x00, 0x00, 0x78, 0x00, 0x04, 0x80, 0x04, 0x00,
+        0x01, 0x00, 0x08, 0x00, 0x02, 0x00, 0x40, 0x00, 0x00, 0x00, 0x68, 0x00, 0x03, 0x80, 0x24,
+        0x00, 0x01, 0x80, 0x08, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x00, 0x02, 0x00,
+        0x74, 0x78, 0x2d, 0x73, 0x63, 0x61, 0x74, 0x74, 0x65, 0x72, 0x2d, 0x67, 0x61, 0x74, 0x68,
+        0x65, 0x72, 0x00, 0x00, 0x00, 0x28, 0x00, 0x01, 0x80, 0x08, 0x00, 0x01, 0x00, 0x0b, 0x00,
+        0x00, 0x00, 0x1c, 0x00, 0x02, 0x00, 0x74, 0x78, 0x2d, 0x67, 0x65, 0x6e, 0x65, 0x72, 0x69,
+        0x63, 0x2d, 0x73, 0x65, 0x67, 0x6d, 0x65, 0x6e, 0x74, 0x61, 0x74, 0x69, 0x6f, 0x6e, 0x00,
+        0x18, 0x00, 0x01, 0x80, 0x08, 0x00, 0x01, 0x00, 0x0e, 0x00, 0x00, 0x00, 0x0b, 0x00, 0x02,
+        0x00, 0x72, 0x78, 0x2d, 0x67, 0x72, 0x6f, 0x00, 0x00,
+    ];
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn active_list() {
+        let attributes = decode(REPLY).unwrap();
+        assert_eq!(
+            active(&attributes).unwrap(),
+            ["tx-scatter-gather", "tx-generic-segmentation", "rx-gro"]
+        );
+    }
+
+    #[test]
+    fn active_mask() {
+        let bit = |name, on| {
+            let mut fields = vec![Attribute::string(ETHTOOL_A_BITSET_BIT_NAME, name)];
+            if on {
+                fields.push(Attribute::new(ETHTOOL_A_BITSET_BIT_VALUE, Vec::new()));
+            }
+            Attribute::nested(ETHTOOL_A_BITSETS_BIT, &fields)
+        };
+
+        let bits = Attribute::nested(
+            ETHTOOL_A_BITSET_BITS,
+            &[bit("rx-gro", true), bit("tx-generic-segmentation", false)],
+        );
+
+        let attributes = [Attribute::nested(ETHTOOL_A_FEATURES_ACTIVE, &[bits])];
+        assert_eq!(active(&attributes).unwrap(), ["rx-gro"]);
+    }
+}
@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generic netlink (GENL) messages
+//!
+//! Only the framing is handled here. Each family (such as ethtool) defines
+//! its own commands and attributes, which are exchanged as raw attributes.
+
+use super::{Connection, Error};
+
+use netlink_packet_core::{NetlinkDeserializable, NetlinkHeader, NetlinkSerializable};
+use netlink_packet_route::{DecodeError, NetlinkMessage, NetlinkPayload, NLM_F_REQUEST};
+
+use log::debug;
+
+use std::io::ErrorKind;
+
+/// The family of the controller, which resolves the other families
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+/// Marks an attribute as containing nested attributes
+pub const NLA_F_NESTED: u16 = 0x8000;
+
+/// Marks an attribute as being in network byte order
+#[allow(dead_code)]
+pub const NLA_F_NET_BYTEORDER: u16 = 0x4000;
+
+/// Rounds `len` up to the netlink alignment
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// A raw netlink attribute
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    kind: u16,
+    value: Vec<u8>,
+}
+
+impl Attribute {
+    #[inline]
+    pub fn new(kind: u16, value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            kind,
+            value: value.into(),
+        }
+    }
+
+    /// Creates an attribute holding a NUL-terminated string
+    pub fn string(kind: u16, value: &str) -> Self {
+        let mut bytes = Vec::from(value);
+        bytes.push(0);
+        Self::new(kind, bytes)
+    }
+
+    /// Creates an attribute holding other attributes
+    pub fn nested(kind: u16, attributes: &[Attribute]) -> Self {
+        Self::new(kind | NLA_F_NESTED, encode(attributes))
+    }
+
+    /// Returns the type of the attribute (without the flags)
+    #[inline]
+    pub fn kind(&self) -> u16 {
+        self.kind & !(NLA_F_NESTED | NLA_F_NET_BYTEORDER)
+    }
+
+    #[inline]
+    #[allow(dead_code)]
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    #[allow(dead_code)]
+    pub fn as_u8(&self) -> Option<u8> {
+        self.value.first().copied()
+    }
+
+    pub fn as_u16(&self) -> Option<u16> {
+        let mut bytes = [0u8; 2];
+        bytes.copy_from_slice(self.value.get(..2)?);
+        Some(u16::from_ne_bytes(bytes))
+    }
+
+    #[allow(dead_code)]
+    pub fn as_u32(&self) -> Option<u32> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.value.get(..4)?);
+        Some(u32::from_ne_bytes(bytes))
+    }
+
+    #[allow(dead_code)]
+    pub fn as_u64(&self) -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.value.get(..8)?);
+        Some(u64::from_ne_bytes(bytes))
+    }
+
+    /// Returns the string held by the attribute (without the NUL)
+    pub fn as_str(&self) -> Option<&str> {
+        let end = self.value.iter().position(|b| *b == 0);
+        std::str::from_utf8(&self.value[..end.unwrap_or(self.value.len())]).ok()
+    }
+
+    /// Decodes the attributes nested within the attribute
+    #[inline]
+    pub fn attributes(&self) -> Result<Vec<Attribute>, DecodeError> {
+        decode(&self.value)
+    }
+}
+
+/// Serializes attributes, padding each to the netlink alignment
+pub fn encode(attributes: &[Attribute]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for attribute in attributes {
+        let len = 4 + attribute.value.len();
+        bytes.extend_from_slice(&(len as u16).to_ne_bytes());
+        bytes.extend_from_slice(&attribute.kind.to_ne_bytes());
+        bytes.extend_from_slice(&attribute.value);
+        bytes.resize(bytes.len() + align(len) - len, 0);
+    }
+    bytes
+}
+
+/// Deserializes a run of attributes
+pub fn decode(mut bytes: &[u8]) -> Result<Vec<Attribute>, DecodeError> {
+    let mut attributes = Vec::new();
+    while bytes.len() >= 4 {
+        let len = usize::from(u16::from_ne_bytes([bytes[0], bytes[1]]));
+        let kind = u16::from_ne_bytes([bytes[2], bytes[3]]);
+        if len < 4 || len > bytes.len() {
+            return Err(format!("invalid attribute length: {}", len).into());
+        }
+
+        attributes.push(Attribute::new(kind, &bytes[4..len]));
+        bytes = &bytes[align(len).min(bytes.len())..];
+    }
+
+    Ok(attributes)
+}
+
+/// A generic netlink message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenericMessage {
+    /// The id of the family (see `Connection::family()`)
+    pub family: u16,
+    pub command: u8,
+    pub version: u8,
+    pub attributes: Vec<Attribute>,
+}
+
+impl NetlinkSerializable<GenericMessage> for GenericMessage {
+    fn message_type(&self) -> u16 {
+        self.family
+    }
+
+    fn buffer_len(&self) -> usize {
+        4 + encode(&self.attributes).len()
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        buffer[0] = self.command;
+        buffer[1] = self.version;
+        buffer[2..4].copy_from_slice(&[0, 0]);
+
+        let attributes = encode(&self.attributes);
+        buffer[4..4 + attributes.len()].copy_from_slice(&attributes);
+    }
+}
+
+impl NetlinkDeserializable<GenericMessage> for GenericMessage {
+    type Error = DecodeError;
+
+    fn deserialize(header: &NetlinkHeader, payload: &[u8]) -> Result<Self, Self::Error> {
+        if payload.len() < 4 {
+            return Err("truncated generic netlink header".into());
+        }
+
+        Ok(Self {
+            family: header.message_type,
+            command: payload[0],
+            version: payload[1],
+            attributes: decode(&payload[4..])?,
+        })
+    }
+}
+
+impl From<GenericMessage> for NetlinkPayload<GenericMessage> {
+    #[inline]
+    fn from(value: GenericMessage) -> Self {
+        NetlinkPayload::InnerMessage(value)
+    }
+}
+
+impl Connection {
+    /// Resolves the id of the generic netlink family `name`
+    ///
+    /// The connection must have been opened with `Connection::generic()`.
+    pub fn family(&mut self, name: &str) -> Result<u16, Error> {
+        debug!("resolving generic netlink family: name={}", name);
+        self.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST,
+                ..Default::default()
+            },
+            payload: GenericMessage {
+                family: GENL_ID_CTRL,
+                command: CTRL_CMD_GETFAMILY,
+                version: 1,
+                attributes: vec![Attribute::string(CTRL_ATTR_FAMILY_NAME, name)],
+            }
+            .into(),
+        })?;
+
+        match self.pull::<GenericMessage>()?.payload {
+            NetlinkPayload::InnerMessage(msg) => msg
+                .attributes
+                .iter()
+                .find(|a| a.kind() == CTRL_ATTR_FAMILY_ID)
+                .and_then(Attribute::as_u16)
+                .ok_or_else(|| ErrorKind::InvalidData.into()),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_pads() {
+        let bytes = encode(&[Attribute::new(1, vec![0xaa]), Attribute::new(2, vec![])]);
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(&bytes[..2], &5u16.to_ne_bytes());
+        assert_eq!(&bytes[8..10], &4u16.to_ne_bytes());
+    }
+
+    #[test]
+    fn decode() {
+        let inner = [Attribute::string(2, "eth0"), Attribute::new(3, vec![1, 0])];
+        let attributes = vec![
+            Attribute::new(1, 7u32.to_ne_bytes().to_vec()),
+            Attribute::nested(4, &inner),
+        ];
+
+        let decoded = super::decode(&encode(&attributes)).unwrap();
+        assert_eq!(decoded, attributes);
+        assert_eq!(decoded[0].as_u32(), Some(7));
+        assert_eq!(decoded[1].kind(), 4);
+
+        let nested = decoded[1].attributes().unwrap();
+        assert_eq!(nested, inner);
+        assert_eq!(nested[0].as_str(), Some("eth0"));
+        assert_eq!(nested[1].as_u16(), Some(u16::from_ne_bytes([1, 0])));
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let mut bytes = encode(&[Attribute::new(1, vec![0; 4])]);
+        bytes[..2].copy_from_slice(&12u16.to_ne_bytes());
+        assert!(super::decode(&bytes).is_err());
+
+        bytes[..2].copy_from_slice(&2u16.to_ne_bytes());
+        assert!(super::decode(&bytes).is_err());
+
+        // Trailing bytes too short for an attribute are ignored.
+        assert_eq!(super::decode(&[0, 0]).unwrap(), []);
+    }
+}
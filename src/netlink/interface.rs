@@ -1,18 +1,184 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{connection::Connection, Address, Error};
+use super::{connection::Connection, Address, AddressOptions, Error};
 
+use log::debug;
 use netlink_packet_route::*;
 
 use std::convert::TryFrom;
 use std::io::ErrorKind;
 use std::net::IpAddr;
 use std::os::unix::io::AsRawFd;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IpvlanMode {
+    L2,
+    L3,
+    L3S,
+}
+
+impl std::fmt::Display for IpvlanMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::L2 => write!(f, "l2"),
+            Self::L3 => write!(f, "l3"),
+            Self::L3S => write!(f, "l3s"),
+        }
+    }
+}
+
+impl FromStr for IpvlanMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "l2" => Ok(Self::L2),
+            "l3" => Ok(Self::L3),
+            "l3s" => Ok(Self::L3S),
+            _ => Err(format!("invalid ipvlan mode: {}", s)),
+        }
+    }
+}
+
+impl From<IpvlanMode> for u16 {
+    fn from(value: IpvlanMode) -> Self {
+        match value {
+            IpvlanMode::L2 => 0,
+            IpvlanMode::L3 => 1,
+            IpvlanMode::L3S => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MacvlanMode {
+    Private,
+    Vepa,
+    Bridge,
+    Passthru,
+}
+
+impl std::fmt::Display for MacvlanMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Private => write!(f, "private"),
+            Self::Vepa => write!(f, "vepa"),
+            Self::Bridge => write!(f, "bridge"),
+            Self::Passthru => write!(f, "passthru"),
+        }
+    }
+}
+
+impl FromStr for MacvlanMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "private" => Ok(Self::Private),
+            "vepa" => Ok(Self::Vepa),
+            "bridge" => Ok(Self::Bridge),
+            "passthru" => Ok(Self::Passthru),
+            _ => Err(format!("invalid macvlan mode: {}", s)),
+        }
+    }
+}
+
+impl From<MacvlanMode> for u32 {
+    fn from(value: MacvlanMode) -> Self {
+        match value {
+            MacvlanMode::Private => 1,
+            MacvlanMode::Vepa => 2,
+            MacvlanMode::Bridge => 4,
+            MacvlanMode::Passthru => 8,
+        }
+    }
+}
+
+/// The traffic counters of an interface
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+/// The attribute type of each entry in a vlan QoS map
+const IFLA_VLAN_QOS_MAPPING: u16 = 1;
+
+/// How a vlan maps priorities (see `Interface::add_vlan_with()`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VlanQos {
+    /// Maps the 802.1p priorities of received frames to packet priorities
+    pub ingress: Vec<(u32, u32)>,
+
+    /// Maps packet priorities to the 802.1p priorities of sent frames
+    pub egress: Vec<(u32, u32)>,
+}
+
+impl Default for VlanQos {
+    /// Preserves priorities 0-7 in both directions
+    fn default() -> Self {
+        let identity: Vec<(u32, u32)> = (0..8).map(|p| (p, p)).collect();
+        Self {
+            ingress: identity.clone(),
+            egress: identity,
+        }
+    }
+}
+
+impl VlanQos {
+    /// Encodes a map as nested `IFLA_VLAN_QOS_MAPPING` attributes
+    fn encode(map: &[(u32, u32)]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(map.len() * 12);
+        for (from, to) in map {
+            bytes.extend_from_slice(&12u16.to_ne_bytes());
+            bytes.extend_from_slice(&IFLA_VLAN_QOS_MAPPING.to_ne_bytes());
+            bytes.extend_from_slice(&from.to_ne_bytes());
+            bytes.extend_from_slice(&to.to_ne_bytes());
+        }
+        bytes
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Interface {
     index: u32,
     alias: String,
+    mtu: u32,
+    mac: Vec<u8>,
+    flags: u32,
+    kind: Option<String>,
+    master: Option<u32>,
+}
+
+/// Returns the name of a kind of link, as used by `ip link`
+fn kind(kind: &link::nlas::InfoKind) -> String {
+    use netlink_packet_route::link::nlas::InfoKind::*;
+
+    match kind {
+        Dummy => "dummy".into(),
+        Bridge => "bridge".into(),
+        Vlan => "vlan".into(),
+        Veth => "veth".into(),
+        Bond => "bond".into(),
+        IpVlan => "ipvlan".into(),
+        MacVlan => "macvlan".into(),
+        IpTun => "ipip".into(),
+        SitTun => "sit".into(),
+        GreTun => "gre".into(),
+        GreTun6 => "ip6gre".into(),
+        GreTap6 => "ip6gretap".into(),
+        Other(name) => name.clone(),
+
+        // The remaining names are those of the variants (e.g. `vxlan`).
+        _ => format!("{:?}", kind).to_lowercase(),
+    }
 }
 
 impl TryFrom<NetlinkPayload<RtnlMessage>> for Interface {
@@ -20,26 +186,55 @@ impl TryFrom<NetlinkPayload<RtnlMessage>> for Interface {
 
     fn try_from(value: NetlinkPayload<RtnlMessage>) -> Result<Self, Self::Error> {
         if let NetlinkPayload::InnerMessage(RtnlMessage::NewLink(msg)) = value {
-            for nla in &msg.nlas {
-                if let link::nlas::Nla::IfName(alias) = nla {
-                    return Ok(Interface {
-                        index: msg.header.index,
-                        alias: alias.into(),
-                    });
+            let mut alias = None;
+            let mut mtu = 0;
+            let mut mac = Vec::new();
+            let mut kind = None;
+            let mut master = None;
+
+            for nla in msg.nlas {
+                match nla {
+                    link::nlas::Nla::IfName(name) => alias = Some(name),
+                    link::nlas::Nla::Mtu(value) => mtu = value,
+                    link::nlas::Nla::Address(value) => mac = value,
+                    link::nlas::Nla::Master(index) => master = Some(index),
+                    link::nlas::Nla::Info(infos) => {
+                        for info in infos {
+                            if let link::nlas::Info::Kind(k) = info {
+                                kind = Some(self::kind(&k));
+                            }
+                        }
+                    }
+                    _ => (),
                 }
             }
+
+            if let Some(alias) = alias {
+                return Ok(Interface {
+                    index: msg.header.index,
+                    alias,
+                    mtu,
+                    mac,
+                    flags: msg.header.flags,
+                    kind,
+                    master,
+                });
+            }
         }
 
         Err(ErrorKind::InvalidData)
     }
 }
 
-impl Interface {
-    //const IPVLAN_MODE_L2: u16 = 0;
-    //const IPVLAN_MODE_L3: u16 = 1;
-    const IPVLAN_MODE_L3S: u16 = 2;
+impl std::fmt::Display for Interface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.alias)
+    }
+}
 
+impl Interface {
     pub fn find(alias: &str) -> Result<Interface, Error> {
+        debug!("finding interface: alias={}", alias);
         let mut nl = Connection::new()?;
         nl.push(NetlinkMessage {
             header: NetlinkHeader {
@@ -56,7 +251,145 @@ impl Interface {
         Ok(Self::try_from(nl.pull()?.payload)?)
     }
 
-    pub fn add_ipvlan(&mut self, alias: &str) -> Result<Self, Error> {
+    /// Finds the interface with the index `index`
+    pub fn from_index(index: u32) -> Result<Interface, Error> {
+        debug!("finding interface: index={}", index);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST,
+                ..Default::default()
+            },
+            payload: RtnlMessage::GetLink(LinkMessage {
+                header: LinkHeader {
+                    index,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .into(),
+        })?;
+
+        Ok(Self::try_from(nl.pull()?.payload)?)
+    }
+
+    /// Lists all interfaces
+    pub fn list() -> Result<Vec<Self>, Error> {
+        debug!("listing interfaces");
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_DUMP,
+                ..Default::default()
+            },
+            payload: RtnlMessage::GetLink(Default::default()).into(),
+        })?;
+
+        let mut interfaces = Vec::new();
+        loop {
+            match nl.pull()?.payload {
+                NetlinkPayload::Done => break Ok(interfaces),
+                payload => interfaces.push(Self::try_from(payload)?),
+            }
+        }
+    }
+
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the name of the interface
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.alias
+    }
+
+    /// Returns the kind of link (e.g. `ipvlan`), if it has one
+    #[inline]
+    pub fn kind(&self) -> Option<&str> {
+        self.kind.as_deref()
+    }
+
+    /// Returns the index of the master (such as a bridge or bond) the
+    /// interface is enslaved to, if any
+    #[inline]
+    pub fn master(&self) -> Option<u32> {
+        self.master
+    }
+
+    /// Returns the interface flags (`IFF_*`)
+    #[inline]
+    #[allow(dead_code)]
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    #[inline]
+    pub fn mtu(&self) -> u32 {
+        self.mtu
+    }
+
+    /// Returns the hardware address
+    #[inline]
+    pub fn mac(&self) -> &[u8] {
+        &self.mac
+    }
+
+    /// Reads the current traffic counters
+    #[allow(dead_code)]
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST,
+                ..Default::default()
+            },
+            payload: RtnlMessage::GetLink(LinkMessage {
+                header: LinkHeader {
+                    index: self.index,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .into(),
+        })?;
+
+        let msg = match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::InnerMessage(RtnlMessage::NewLink(msg)) => msg,
+            _ => return Err(ErrorKind::InvalidData.into()),
+        };
+
+        for nla in msg.nlas {
+            if let link::nlas::Nla::Stats64(stats) = nla {
+                return Ok(Stats {
+                    rx_bytes: stats.rx_bytes,
+                    rx_packets: stats.rx_packets,
+                    rx_errors: stats.rx_errors,
+                    rx_dropped: stats.rx_dropped,
+                    tx_bytes: stats.tx_bytes,
+                    tx_packets: stats.tx_packets,
+                    tx_errors: stats.tx_errors,
+                    tx_dropped: stats.tx_dropped,
+                });
+            }
+        }
+
+        Err(ErrorKind::InvalidData.into())
+    }
+
+    /// Whether the interface is operationally up (e.g. it has a carrier)
+    #[inline]
+    #[allow(dead_code)]
+    pub fn running(&self) -> bool {
+        self.flags & IFF_RUNNING != 0
+    }
+
+    pub fn add_ipvlan(&mut self, alias: &str, mode: IpvlanMode) -> Result<Self, Error> {
+        debug!(
+            "adding ipvlan: parent={} alias={} mode={}",
+            self, alias, mode
+        );
         let mut nl = Connection::new()?;
         nl.push(NetlinkMessage {
             header: NetlinkHeader {
@@ -70,7 +403,7 @@ impl Interface {
                     link::nlas::Nla::Info(vec![
                         link::nlas::Info::Kind(link::nlas::InfoKind::IpVlan),
                         link::nlas::Info::Data(link::nlas::InfoData::IpVlan(vec![
-                            link::nlas::InfoIpVlan::Mode(Self::IPVLAN_MODE_L3S),
+                            link::nlas::InfoIpVlan::Mode(mode.into()),
                             link::nlas::InfoIpVlan::Flags(0),
                         ])),
                     ]),
@@ -86,12 +419,340 @@ impl Interface {
         }
     }
 
+    /// Adds a macvlan with the hardware address `mac` (or a random one)
+    pub fn add_macvlan(
+        &mut self,
+        alias: &str,
+        mode: MacvlanMode,
+        mac: Option<[u8; 6]>,
+    ) -> Result<Self, Error> {
+        debug!(
+            "adding macvlan: parent={} alias={} mode={} mac={:?}",
+            self, alias, mode, mac
+        );
+        let mut nlas = vec![
+            link::nlas::Nla::Link(self.index),
+            link::nlas::Nla::IfName(alias.into()),
+            link::nlas::Nla::Info(vec![
+                link::nlas::Info::Kind(link::nlas::InfoKind::MacVlan),
+                link::nlas::Info::Data(link::nlas::InfoData::MacVlan(vec![
+                    link::nlas::InfoMacVlan::Mode(mode.into()),
+                ])),
+            ]),
+        ];
+        if let Some(mac) = mac {
+            nlas.push(link::nlas::Nla::Address(mac.to_vec()));
+        }
+
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                ..Default::default()
+            },
+            payload: RtnlMessage::NewLink(LinkMessage {
+                nlas,
+                ..Default::default()
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(Interface::find(alias)?),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Adds an 802.1Q vlan over the interface, preserving priorities
+    pub fn add_vlan(&mut self, alias: &str, id: u16) -> Result<Self, Error> {
+        self.add_vlan_with(alias, id, &VlanQos::default())
+    }
+
+    /// Adds an 802.1Q vlan over the interface with the given priority maps
+    pub fn add_vlan_with(&mut self, alias: &str, id: u16, qos: &VlanQos) -> Result<Self, Error> {
+        debug!(
+            "adding vlan: parent={} alias={} id={} qos={:?}",
+            self, alias, id, qos
+        );
+        if id == 0 || id > 4094 {
+            return Err(Error::Io(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid vlan id: {}", id),
+            )));
+        }
+
+        let mut data = vec![link::nlas::InfoVlan::Id(id)];
+        if !qos.ingress.is_empty() {
+            data.push(link::nlas::InfoVlan::IngressQos(VlanQos::encode(
+                &qos.ingress,
+            )));
+        }
+        if !qos.egress.is_empty() {
+            data.push(link::nlas::InfoVlan::EgressQos(VlanQos::encode(
+                &qos.egress,
+            )));
+        }
+
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                ..Default::default()
+            },
+            payload: RtnlMessage::NewLink(LinkMessage {
+                nlas: vec![
+                    link::nlas::Nla::Link(self.index),
+                    link::nlas::Nla::IfName(alias.into()),
+                    link::nlas::Nla::Info(vec![
+                        link::nlas::Info::Kind(link::nlas::InfoKind::Vlan),
+                        link::nlas::Info::Data(link::nlas::InfoData::Vlan(data)),
+                    ]),
+                ],
+                ..Default::default()
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(Interface::find(alias)?),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Adds a dummy interface in the current namespace
+    ///
+    /// Dummy interfaces suit addresses (such as VIPs and anycast addresses)
+    /// which must be local without belonging to any real link: they never
+    /// send or receive packets and, being `NOARP`, skip duplicate address
+    /// detection.
+    pub fn add_dummy(alias: &str) -> Result<Self, Error> {
+        debug!("adding dummy: alias={}", alias);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                ..Default::default()
+            },
+            payload: RtnlMessage::NewLink(LinkMessage {
+                nlas: vec![
+                    link::nlas::Nla::IfName(alias.into()),
+                    link::nlas::Nla::Info(vec![link::nlas::Info::Kind(
+                        link::nlas::InfoKind::Dummy,
+                    )]),
+                ],
+                ..Default::default()
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(Interface::find(alias)?),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Adds a bridge, to which ports are added with `set_master()`
+    #[allow(dead_code)]
+    pub fn add_bridge(alias: &str) -> Result<Self, Error> {
+        debug!("adding bridge: alias={}", alias);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                ..Default::default()
+            },
+            payload: RtnlMessage::NewLink(LinkMessage {
+                nlas: vec![
+                    link::nlas::Nla::IfName(alias.into()),
+                    link::nlas::Nla::Info(vec![link::nlas::Info::Kind(
+                        link::nlas::InfoKind::Bridge,
+                    )]),
+                ],
+                ..Default::default()
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(Interface::find(alias)?),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Lists the ports of the interface, which must be a bridge
+    #[allow(dead_code)]
+    pub fn ports(&self) -> Result<Vec<Self>, Error> {
+        if self.kind() != Some("bridge") {
+            return Err(Error::Io(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a bridge", self),
+            )));
+        }
+
+        self.slaves()
+    }
+
+    /// Lists the interfaces enslaved to the interface (such as the ports of
+    /// a bridge or the members of a bond)
+    #[allow(dead_code)]
+    pub fn slaves(&self) -> Result<Vec<Self>, Error> {
+        let mut slaves = Interface::list()?;
+        slaves.retain(|i| i.master == Some(self.index));
+        Ok(slaves)
+    }
+
+    /// Adds a veth pair, returning both ends
+    ///
+    /// Either end can then be moved with `move_to_namespace()`.
+    pub fn add_veth(alias: &str, peer: &str) -> Result<(Self, Self), Error> {
+        debug!("adding veth: alias={} peer={}", alias, peer);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                ..Default::default()
+            },
+            payload: RtnlMessage::NewLink(LinkMessage {
+                nlas: vec![
+                    link::nlas::Nla::IfName(alias.into()),
+                    link::nlas::Nla::Info(vec![
+                        link::nlas::Info::Kind(link::nlas::InfoKind::Veth),
+                        link::nlas::Info::Data(link::nlas::InfoData::Veth(
+                            link::nlas::VethInfo::Peer(LinkMessage {
+                                nlas: vec![link::nlas::Nla::IfName(peer.into())],
+                                ..Default::default()
+                            }),
+                        )),
+                    ]),
+                ],
+                ..Default::default()
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok((Interface::find(alias)?, Interface::find(peer)?)),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Adds a veth pair whose peer is created directly in the namespace
+    /// `nsfd`, returning the local end
+    ///
+    /// Unlike moving the peer afterwards, the peer never appears in the
+    /// current namespace, so its name can't collide with one here.
+    #[allow(dead_code)]
+    pub fn add_veth_in(alias: &str, peer: &str, nsfd: &impl AsRawFd) -> Result<Self, Error> {
+        debug!(
+            "adding veth: alias={} peer={} nsfd={}",
+            alias,
+            peer,
+            nsfd.as_raw_fd()
+        );
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                ..Default::default()
+            },
+            payload: RtnlMessage::NewLink(LinkMessage {
+                nlas: vec![
+                    link::nlas::Nla::IfName(alias.into()),
+                    link::nlas::Nla::Info(vec![
+                        link::nlas::Info::Kind(link::nlas::InfoKind::Veth),
+                        link::nlas::Info::Data(link::nlas::InfoData::Veth(
+                            link::nlas::VethInfo::Peer(LinkMessage {
+                                nlas: vec![
+                                    link::nlas::Nla::IfName(peer.into()),
+                                    link::nlas::Nla::NetNsFd(nsfd.as_raw_fd()),
+                                ],
+                                ..Default::default()
+                            }),
+                        )),
+                    ]),
+                ],
+                ..Default::default()
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(Interface::find(alias)?),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Enslaves the interface to `master` (such as a bridge)
+    pub fn set_master(&mut self, master: &Interface) -> Result<(), Error> {
+        debug!("setting master: interface={} master={}", self, master);
+        self.enslave(master.index)
+    }
+
+    /// Releases the interface from its master (if any)
+    #[allow(dead_code)]
+    pub fn clear_master(&mut self) -> Result<(), Error> {
+        debug!("clearing master: interface={}", self);
+        self.enslave(0)
+    }
+
+    /// Sets the index of the master (zero for none)
+    fn enslave(&mut self, index: u32) -> Result<(), Error> {
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK,
+                ..Default::default()
+            },
+            payload: RtnlMessage::SetLink(LinkMessage {
+                header: LinkHeader {
+                    index: self.index,
+                    ..Default::default()
+                },
+                nlas: vec![link::nlas::Nla::Master(index)],
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => {
+                self.master = Some(index).filter(|i| *i != 0);
+                Ok(())
+            }
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
     pub fn add_address(&mut self, address: IpAddr, prefix: u8) -> Result<Address, Error> {
+        self.add_address_with(address, prefix, AddressOptions::default())
+    }
+
+    /// Adds an address with non-default options
+    pub fn add_address_with(
+        &mut self,
+        address: IpAddr,
+        prefix: u8,
+        options: AddressOptions,
+    ) -> Result<Address, Error> {
         let bytes: Vec<u8> = match address {
             IpAddr::V4(x) => x.octets().into(),
             IpAddr::V6(x) => x.octets().into(),
         };
 
+        let mut nlas = vec![
+            address::Nla::Address(bytes.clone()),
+            address::Nla::Local(bytes),
+        ];
+        if options.flags != 0 {
+            nlas.push(address::Nla::Flags(options.flags));
+        }
+        if let Some(info) = options.cache_info() {
+            nlas.push(address::Nla::CacheInfo(info));
+        }
+
+        debug!(
+            "adding address: interface={} address={}/{} options={:?}",
+            self, address, prefix, options
+        );
         let mut nl = Connection::new()?;
         nl.push(NetlinkMessage {
             header: NetlinkHeader {
@@ -106,23 +767,33 @@ impl Interface {
                         IpAddr::V4(..) => AF_INET as _,
                         IpAddr::V6(..) => AF_INET6 as _,
                     },
+                    scope: options.scope,
                     ..Default::default()
                 },
-                nlas: vec![
-                    address::Nla::Address(bytes.clone()),
-                    address::Nla::Local(bytes),
-                ],
+                nlas,
             })
             .into(),
         })?;
 
         match nl.pull::<RtnlMessage>()?.payload {
-            NetlinkPayload::Ack(..) => Ok(Address::new(self.index, address, prefix)),
+            NetlinkPayload::Ack(..) => {
+                let added = Address::new(self.index, address, prefix)
+                    .with_flags(options.flags)
+                    .with_scope(options.scope)
+                    .with_lifetimes(options.preferred, options.valid);
+                Ok(added)
+            }
             _ => Err(ErrorKind::InvalidData.into()),
         }
     }
 
+    #[allow(dead_code)]
+    pub fn del_address(&mut self, address: IpAddr, prefix: u8) -> Result<(), Error> {
+        Address::new(self.index, address, prefix).delete()
+    }
+
     pub fn delete(self) -> Result<(), (Self, Error)> {
+        debug!("deleting interface: interface={}", self);
         fn inner(iface: &Interface) -> Result<(), Error> {
             let mut nl = Connection::new()?;
             nl.push(NetlinkMessage {
@@ -152,7 +823,24 @@ impl Interface {
         }
     }
 
+    /// Deletes the interface, which must be a vlan
+    #[allow(dead_code)]
+    pub fn del_vlan(self) -> Result<(), (Self, Error)> {
+        if self.kind() != Some("vlan") {
+            let error =
+                std::io::Error::new(ErrorKind::InvalidInput, format!("{} is not a vlan", self));
+            return Err((self, Error::Io(error)));
+        }
+
+        self.delete()
+    }
+
     pub fn move_to_namespace(self, nsfd: &impl AsRawFd) -> Result<(), (Self, Error)> {
+        debug!(
+            "moving interface: interface={} nsfd={}",
+            self,
+            nsfd.as_raw_fd()
+        );
         fn inner(iface: &Interface, nsfd: &impl AsRawFd) -> Result<(), Error> {
             let mut nl = Connection::new()?;
             nl.push(NetlinkMessage {
@@ -182,53 +870,125 @@ impl Interface {
         }
     }
 
-    pub fn up(&self) -> Result<(), Error> {
+    /// Renames the interface (which must be down)
+    pub fn rename(&mut self, alias: &str) -> Result<(), Error> {
+        debug!("renaming interface: interface={} alias={}", self, alias);
         let mut nl = Connection::new()?;
         nl.push(NetlinkMessage {
             header: NetlinkHeader {
                 flags: NLM_F_REQUEST | NLM_F_ACK,
                 ..Default::default()
             },
-            payload: RtnlMessage::NewLink(LinkMessage {
+            payload: RtnlMessage::SetLink(LinkMessage {
+                header: LinkHeader {
+                    index: self.index,
+                    ..Default::default()
+                },
+                nlas: vec![link::nlas::Nla::IfName(alias.into())],
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => {
+                self.alias = alias.into();
+                Ok(())
+            }
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Sets the hardware address
+    ///
+    /// The address must be unicast and as long as the current one. Ipvlan
+    /// interfaces always share the address of their parent.
+    #[allow(dead_code)]
+    pub fn set_mac(&mut self, mac: &[u8]) -> Result<(), Error> {
+        debug!("setting mac: interface={} mac={:02x?}", self, mac);
+        if self.kind() == Some("ipvlan") {
+            return Err(Error::Io(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} can't have its own hardware address", self),
+            )));
+        }
+
+        let zero = mac.iter().all(|o| *o == 0);
+        let multicast = mac.first().map_or(true, |o| o & 0x01 != 0);
+        if mac.len() != self.mac.len() || zero || multicast {
+            return Err(Error::Io(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid hardware address for {}: {:02x?}", self, mac),
+            )));
+        }
+
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK,
+                ..Default::default()
+            },
+            payload: RtnlMessage::SetLink(LinkMessage {
                 header: LinkHeader {
                     index: self.index,
-                    flags: IFF_UP,
                     ..Default::default()
                 },
+                nlas: vec![link::nlas::Nla::Address(mac.into())],
+            })
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => {
+                self.mac = mac.into();
+                Ok(())
+            }
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Sets the MTU, returning the one the kernel actually applied
+    pub fn set_mtu(&mut self, mtu: u32) -> Result<u32, Error> {
+        debug!("setting mtu: interface={} mtu={}", self, mtu);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK,
                 ..Default::default()
+            },
+            payload: RtnlMessage::SetLink(LinkMessage {
+                header: LinkHeader {
+                    index: self.index,
+                    ..Default::default()
+                },
+                nlas: vec![link::nlas::Nla::Mtu(mtu)],
             })
             .into(),
         })?;
 
         match nl.pull::<RtnlMessage>()?.payload {
-            NetlinkPayload::Ack(..) => Ok(()),
+            NetlinkPayload::Ack(..) => {
+                self.mtu = Interface::find(&self.alias)?.mtu;
+                Ok(self.mtu)
+            }
             _ => Err(ErrorKind::InvalidData.into()),
         }
     }
 
-    pub fn add_gateway(&mut self, address: IpAddr) -> Result<(), Error> {
+    pub fn up(&self) -> Result<(), Error> {
+        debug!("bringing up interface: interface={}", self);
         let mut nl = Connection::new()?;
         nl.push(NetlinkMessage {
             header: NetlinkHeader {
-                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                flags: NLM_F_REQUEST | NLM_F_ACK,
                 ..Default::default()
             },
-            payload: RtnlMessage::NewRoute(RouteMessage {
-                header: RouteHeader {
-                    kind: RTN_UNICAST,
-                    address_family: match address {
-                        IpAddr::V4(..) => AF_INET as u8,
-                        IpAddr::V6(..) => AF_INET6 as u8,
-                    },
+            payload: RtnlMessage::NewLink(LinkMessage {
+                header: LinkHeader {
+                    index: self.index,
+                    flags: IFF_UP,
                     ..Default::default()
                 },
-                nlas: vec![
-                    route::Nla::Gateway(match address {
-                        IpAddr::V4(addr) => addr.octets().into(),
-                        IpAddr::V6(addr) => addr.octets().into(),
-                    }),
-                    route::Nla::Oif(self.index),
-                ],
+                ..Default::default()
             })
             .into(),
         })?;
@@ -239,3 +999,47 @@ impl Interface {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::generic::decode;
+    use super::*;
+
+    #[test]
+    fn vlan_qos_encode() {
+        let bytes = VlanQos::encode(&[(0, 0), (5, 3)]);
+        let attributes = decode(&bytes).unwrap();
+        assert_eq!(attributes.len(), 2);
+
+        for (attribute, (from, to)) in attributes.iter().zip(&[(0u32, 0u32), (5, 3)]) {
+            assert_eq!(attribute.kind(), IFLA_VLAN_QOS_MAPPING);
+            let mut expected = from.to_ne_bytes().to_vec();
+            expected.extend_from_slice(&to.to_ne_bytes());
+            assert_eq!(attribute.value(), &expected[..]);
+        }
+
+        assert!(VlanQos::encode(&[]).is_empty());
+    }
+
+    #[test]
+    fn vlan_qos_default() {
+        let qos = VlanQos::default();
+        assert_eq!(qos.ingress.len(), 8);
+        assert!(qos.ingress.iter().all(|(from, to)| from == to));
+        assert_eq!(qos.ingress, qos.egress);
+    }
+
+    #[test]
+    fn kind_names() {
+        use link::nlas::InfoKind::*;
+
+        assert_eq!(kind(&IpVlan), "ipvlan");
+        assert_eq!(kind(&IpTun), "ipip");
+        assert_eq!(kind(&SitTun), "sit");
+        assert_eq!(kind(&GreTun), "gre");
+        assert_eq!(kind(&GreTun6), "ip6gre");
+        assert_eq!(kind(&GreTap6), "ip6gretap");
+        assert_eq!(kind(&Vxlan), "vxlan");
+        assert_eq!(kind(&Other("wireguard".into())), "wireguard");
+    }
+}
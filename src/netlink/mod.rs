@@ -1,26 +1,164 @@
 // SPDX-License-Identifier: Apache-2.0
 
+//! A small rtnetlink API
+//!
+//! This covers more than the command itself uses, so that new features
+//! have the primitives they need. The parts nothing uses yet are marked with
+//! `#[allow(dead_code)]` individually.
+
 mod address;
 mod connection;
+mod ethtool;
+mod generic;
 mod interface;
+mod monitor;
+mod neighbor;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod qdisc;
+mod route;
 mod subnet;
 
-pub use address::Address;
-use connection::Connection;
-pub use interface::Interface;
+pub use address::{
+    Address, AddressFilter, AddressOptions, IFA_F_NODAD, IFA_F_NOPREFIXROUTE, IFA_F_PERMANENT,
+};
+pub use address::{RT_SCOPE_HOST, RT_SCOPE_LINK, RT_SCOPE_UNIVERSE};
+pub use connection::Connection;
+pub use ethtool::Offload;
+pub use generic::{Attribute, GenericMessage};
+pub use interface::{Interface, IpvlanMode, MacvlanMode, Stats, VlanQos};
+pub use monitor::{Event, Monitor};
+pub use neighbor::Neighbor;
+#[cfg(feature = "async")]
+pub use nonblocking::AsyncConnection;
+pub use qdisc::Qdisc;
+pub use route::{Route, RouteKind, RT_TABLE_MAIN};
 pub use subnet::Subnet;
 
+/// Why a request failed, decoded from its errno
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Reason {
+    /// The object already exists (`EEXIST`)
+    AlreadyExists,
+
+    /// The interface doesn't exist (`ENODEV`)
+    NoSuchDevice,
+
+    /// The object doesn't exist (`ENOENT`, `ESRCH`, `EADDRNOTAVAIL`)
+    NotFound,
+
+    /// The caller lacks a capability (`EPERM`, `EACCES`)
+    PermissionDenied,
+
+    /// The object is in use (`EBUSY`)
+    Busy,
+
+    /// The request is malformed or out of range (`EINVAL`, `ERANGE`)
+    InvalidArgument,
+
+    /// The kernel lacks the feature (`EOPNOTSUPP`, `EAFNOSUPPORT`, ...)
+    NotSupported,
+
+    /// Any other errno
+    Other(i32),
+}
+
+impl From<i32> for Reason {
+    fn from(errno: i32) -> Self {
+        match errno {
+            libc::EEXIST => Self::AlreadyExists,
+            libc::ENODEV => Self::NoSuchDevice,
+            libc::ENOENT | libc::ESRCH | libc::EADDRNOTAVAIL => Self::NotFound,
+            libc::EPERM | libc::EACCES => Self::PermissionDenied,
+            libc::EBUSY => Self::Busy,
+            libc::EINVAL | libc::ERANGE => Self::InvalidArgument,
+            libc::EOPNOTSUPP | libc::EAFNOSUPPORT | libc::EPROTONOSUPPORT => Self::NotSupported,
+            errno => Self::Other(errno),
+        }
+    }
+}
+
+impl Reason {
+    /// Returns the (most common) errno of the reason
+    #[allow(dead_code)]
+    pub fn errno(self) -> i32 {
+        match self {
+            Self::AlreadyExists => libc::EEXIST,
+            Self::NoSuchDevice => libc::ENODEV,
+            Self::NotFound => libc::ENOENT,
+            Self::PermissionDenied => libc::EPERM,
+            Self::Busy => libc::EBUSY,
+            Self::InvalidArgument => libc::EINVAL,
+            Self::NotSupported => libc::EOPNOTSUPP,
+            Self::Other(errno) => errno,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
     Decode(netlink_packet_route::DecodeError),
+
+    /// An error reported by the kernel, along with the explanation and the
+    /// offset of the offending attribute from its extended acknowledgement
+    Kernel {
+        reason: Reason,
+        error: std::io::Error,
+        message: Option<String>,
+        offset: Option<u32>,
+    },
 }
 
+impl Error {
+    /// Returns why the request failed, if the errno is known
+    pub fn reason(&self) -> Option<Reason> {
+        match self {
+            Error::Io(e) => e.raw_os_error().map(Reason::from),
+            Error::Decode(..) => None,
+            Error::Kernel { reason, .. } => Some(*reason),
+        }
+    }
+
+    /// Whether the request failed because its object already exists
+    #[inline]
+    pub fn exists(&self) -> bool {
+        self.reason() == Some(Reason::AlreadyExists)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Decode(e) => write!(f, "invalid netlink message: {}", e),
+            Error::Kernel {
+                error,
+                message,
+                offset,
+                ..
+            } => {
+                write!(f, "{}", error)?;
+                if let Some(message) = message {
+                    write!(f, ": {}", message)?;
+                }
+                if let Some(offset) = offset {
+                    write!(f, " (attribute at offset {})", offset)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<Error> for std::io::Error {
     #[inline]
     fn from(value: Error) -> Self {
         match value {
-            Error::Decode(..) => std::io::ErrorKind::InvalidInput.into(),
+            Error::Decode(..) => Self::new(std::io::ErrorKind::InvalidData, value),
+            Error::Kernel { ref error, .. } => Self::new(error.kind(), value.to_string()),
             Error::Io(e) => e,
         }
     }
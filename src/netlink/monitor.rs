@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Address, Connection, Error, Interface};
+
+use netlink_packet_route::*;
+
+use std::convert::TryFrom;
+
+/// A change to the links or addresses of the network namespace
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub enum Event {
+    /// A link was added or changed (e.g. it lost its carrier)
+    Link(Interface),
+
+    /// The link with the index was removed
+    LinkRemoved(u32),
+
+    /// An address was added or changed (e.g. it failed duplicate detection)
+    Address(Address),
+
+    /// An address was removed
+    AddressRemoved(Address),
+}
+
+/// Receives the link and address events of the current network namespace
+#[allow(dead_code)]
+pub struct Monitor(Connection);
+
+#[allow(dead_code)]
+impl Monitor {
+    pub fn new() -> Result<Self, Error> {
+        let mut nl = Connection::new()?;
+        for group in &[RTNLGRP_LINK, RTNLGRP_IPV4_IFADDR, RTNLGRP_IPV6_IFADDR] {
+            nl.subscribe(*group)?;
+        }
+
+        Ok(Self(nl))
+    }
+}
+
+impl Iterator for Monitor {
+    type Item = Result<Event, Error>;
+
+    /// Waits for the next event
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let payload = match self.0.pull::<RtnlMessage>() {
+                Ok(msg) => msg.payload,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let event = match payload {
+                NetlinkPayload::InnerMessage(RtnlMessage::NewLink(..)) => {
+                    Interface::try_from(payload).map(Event::Link)
+                }
+
+                NetlinkPayload::InnerMessage(RtnlMessage::DelLink(msg)) => {
+                    Ok(Event::LinkRemoved(msg.header.index))
+                }
+
+                NetlinkPayload::InnerMessage(RtnlMessage::NewAddress(msg)) => {
+                    match Address::decode(&msg) {
+                        Some(address) => Ok(Event::Address(address)),
+                        None => continue,
+                    }
+                }
+
+                NetlinkPayload::InnerMessage(RtnlMessage::DelAddress(msg)) => {
+                    match Address::decode(&msg) {
+                        Some(address) => Ok(Event::AddressRemoved(address)),
+                        None => continue,
+                    }
+                }
+
+                _ => continue,
+            };
+
+            return Some(event.map_err(Error::from));
+        }
+    }
+}
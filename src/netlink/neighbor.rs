@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Connection, Error, Interface};
+
+use log::debug;
+use netlink_packet_route::*;
+
+use std::io::ErrorKind;
+use std::net::IpAddr;
+
+/// An entry in the neighbor (ARP or NDP) table
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[allow(dead_code)]
+pub struct Neighbor {
+    index: u32,
+    address: IpAddr,
+    mac: Vec<u8>,
+    state: u16,
+}
+
+impl std::fmt::Display for Neighbor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let octets: Vec<String> = self.mac.iter().map(|o| format!("{:02x}", o)).collect();
+        write!(f, "{} lladdr {}", self.address, octets.join(":"))
+    }
+}
+
+#[allow(dead_code)]
+impl Neighbor {
+    fn message(index: u32, address: IpAddr, mac: &[u8], state: u16) -> NeighbourMessage {
+        let (family, bytes): (u16, Vec<u8>) = match address {
+            IpAddr::V4(addr) => (AF_INET, addr.octets().into()),
+            IpAddr::V6(addr) => (AF_INET6, addr.octets().into()),
+        };
+
+        let mut nlas = vec![neighbour::Nla::Destination(bytes)];
+        if !mac.is_empty() {
+            nlas.push(neighbour::Nla::LinkLocalAddress(mac.into()));
+        }
+
+        NeighbourMessage {
+            header: NeighbourHeader {
+                family: family as u8,
+                ifindex: index,
+                state,
+                ..Default::default()
+            },
+            nlas,
+        }
+    }
+
+    /// Lists the entries of the neighbor tables
+    pub fn list() -> Result<Vec<Self>, Error> {
+        debug!("listing neighbors");
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_DUMP,
+                ..Default::default()
+            },
+            payload: RtnlMessage::GetNeighbour(Default::default()).into(),
+        })?;
+
+        let mut neighbors = Vec::new();
+        loop {
+            let msg = match nl.pull()?.payload {
+                NetlinkPayload::Done => break Ok(neighbors),
+                NetlinkPayload::InnerMessage(RtnlMessage::NewNeighbour(msg)) => msg,
+                _ => return Err(ErrorKind::InvalidData.into()),
+            };
+
+            let mut address = None;
+            let mut mac = Vec::new();
+            for nla in msg.nlas {
+                match nla {
+                    neighbour::Nla::Destination(bytes) => {
+                        address = match (u16::from(msg.header.family), bytes.len()) {
+                            (AF_INET, 4) => {
+                                let mut octets = [0u8; 4];
+                                octets.copy_from_slice(&bytes);
+                                Some(IpAddr::V4(octets.into()))
+                            }
+
+                            (AF_INET6, 16) => {
+                                let mut octets = [0u8; 16];
+                                octets.copy_from_slice(&bytes);
+                                Some(IpAddr::V6(octets.into()))
+                            }
+
+                            _ => None,
+                        }
+                    }
+
+                    neighbour::Nla::LinkLocalAddress(bytes) => mac = bytes,
+                    _ => (),
+                }
+            }
+
+            if let Some(address) = address {
+                neighbors.push(Self {
+                    index: msg.header.ifindex,
+                    address,
+                    mac,
+                    state: msg.header.state,
+                });
+            }
+        }
+    }
+
+    /// Adds a permanent entry for `address` at `mac` on `interface`
+    pub fn add(interface: &Interface, address: IpAddr, mac: &[u8]) -> Result<Self, Error> {
+        let neighbor = Self {
+            index: interface.index(),
+            address,
+            mac: mac.into(),
+            state: NUD_PERMANENT,
+        };
+
+        debug!(
+            "adding neighbor: interface={} neighbor={}",
+            interface, neighbor
+        );
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                ..Default::default()
+            },
+            payload: RtnlMessage::NewNeighbour(Self::message(
+                neighbor.index,
+                address,
+                mac,
+                NUD_PERMANENT,
+            ))
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(neighbor),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Deletes the entry
+    pub fn delete(self) -> Result<(), Error> {
+        debug!("deleting neighbor: index={} neighbor={}", self.index, self);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK,
+                ..Default::default()
+            },
+            payload: RtnlMessage::DelNeighbour(Self::message(self.index, self.address, &[], 0))
+                .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(()),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Returns the index of the interface the entry belongs to
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    #[inline]
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    /// Returns the hardware address (empty if unresolved)
+    #[inline]
+    pub fn mac(&self) -> &[u8] {
+        &self.mac
+    }
+
+    /// Returns the state (`NUD_*`) of the entry
+    #[inline]
+    pub fn state(&self) -> u16 {
+        self.state
+    }
+
+    /// Whether the entry was added statically
+    #[inline]
+    pub fn permanent(&self) -> bool {
+        self.state & NUD_PERMANENT != 0
+    }
+}
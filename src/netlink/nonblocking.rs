@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! An asynchronous variant of `Connection` for long-running modes
+//!
+//! The one-shot command keeps using the blocking `Connection`.
+
+use super::connection::{decode, encode, receive, socket};
+use super::Error;
+
+use netlink_packet_core::{NetlinkDeserializable, NetlinkSerializable};
+use netlink_packet_route::NetlinkMessage;
+use netlink_sys::protocols::NETLINK_ROUTE;
+use netlink_sys::Socket;
+use tokio::io::unix::AsyncFd;
+
+#[allow(dead_code)]
+pub struct AsyncConnection {
+    socket: AsyncFd<Socket>,
+    buffer: Vec<u8>,
+    first: usize,
+    last: usize,
+    sequence: u32,
+}
+
+#[allow(dead_code)]
+impl AsyncConnection {
+    /// Opens a connection (which must be done within a tokio runtime)
+    pub fn new() -> std::io::Result<Self> {
+        let socket = socket(NETLINK_ROUTE)?;
+        socket.set_non_blocking(true)?;
+
+        Ok(Self {
+            socket: AsyncFd::new(socket)?,
+            buffer: vec![0u8; 4096],
+            first: 0,
+            last: 0,
+            sequence: 0,
+        })
+    }
+
+    pub async fn push<I>(&mut self, msg: NetlinkMessage<I>) -> std::io::Result<usize>
+    where
+        I: std::fmt::Debug + PartialEq<I> + Eq + Clone + NetlinkSerializable<I>,
+    {
+        let buffer = encode(&mut self.sequence, msg);
+
+        loop {
+            let mut guard = self.socket.writable().await?;
+            if let Ok(result) = guard.try_io(|s| s.get_ref().send(&buffer, 0)) {
+                return result;
+            }
+        }
+    }
+
+    pub async fn pull<I>(&mut self) -> Result<NetlinkMessage<I>, Error>
+    where
+        I: std::fmt::Debug + PartialEq<I> + Eq + Clone + NetlinkDeserializable<I>,
+    {
+        loop {
+            while self.first == self.last {
+                let mut guard = self.socket.readable().await?;
+                let buffer = &mut self.buffer;
+                if let Ok(result) = guard.try_io(|s| receive(s.get_ref(), buffer)) {
+                    self.last = result?;
+                    self.first = 0;
+                }
+            }
+
+            let buffer = &self.buffer[..self.last];
+            if let Some(msg) = decode(buffer, &mut self.first, self.sequence)? {
+                return Ok(msg);
+            }
+        }
+    }
+}
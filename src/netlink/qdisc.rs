@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Root queueing disciplines, for shaping the traffic of an interface
+
+use super::generic::{encode, Attribute};
+use super::{Connection, Error, Interface};
+
+use log::debug;
+use netlink_packet_core::NetlinkSerializable;
+use netlink_packet_route::{NetlinkHeader, NetlinkMessage, NetlinkPayload, RtnlMessage};
+use netlink_packet_route::{NLM_F_ACK, NLM_F_CREATE, NLM_F_REPLACE, NLM_F_REQUEST};
+
+use std::io::ErrorKind;
+use std::time::Duration;
+
+const RTM_NEWQDISC: u16 = 36;
+
+/// The parent of a root queueing discipline
+const TC_H_ROOT: u32 = 0xffff_ffff;
+
+/// The handle `1:` given to the queueing discipline
+const HANDLE: u32 = 0x0001_0000;
+
+const TCA_KIND: u16 = 1;
+const TCA_OPTIONS: u16 = 2;
+
+const TCA_TBF_PARMS: u16 = 1;
+const TCA_TBF_RATE64: u16 = 4;
+const TCA_TBF_BURST: u16 = 6;
+
+/// Rates which are link layer aware need no rate table
+const TC_LINKLAYER_ETHERNET: u8 = 1;
+
+/// A queueing discipline for the root of an interface
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Qdisc {
+    /// A token bucket filter, capping the bandwidth
+    Tbf {
+        /// The sustained rate (in bits per second)
+        rate: u64,
+
+        /// The bytes which may be sent at once above the rate
+        burst: u32,
+
+        /// How long packets may wait before being dropped
+        latency: Duration,
+    },
+
+    /// Fair queueing with controlled delay, which doesn't cap bandwidth but
+    /// keeps flows from starving each other
+    #[allow(dead_code)]
+    FqCodel,
+}
+
+impl std::fmt::Display for Qdisc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tbf {
+                rate,
+                burst,
+                latency,
+            } => write!(
+                f,
+                "tbf rate {}bit burst {}b latency {}ms",
+                rate,
+                burst,
+                latency.as_millis()
+            ),
+            Self::FqCodel => write!(f, "fq_codel"),
+        }
+    }
+}
+
+impl Qdisc {
+    /// Returns the kind and options attributes
+    fn attributes(&self) -> Vec<Attribute> {
+        match *self {
+            Self::Tbf {
+                rate,
+                burst,
+                latency,
+            } => {
+                let bytes = rate / 8;
+
+                // struct tc_ratespec: cell_log, linklayer, overhead,
+                // cell_align, mpu, rate (saturated if it needs RATE64)
+                let mut spec = vec![0u8, TC_LINKLAYER_ETHERNET];
+                spec.extend_from_slice(&[0u8; 6]);
+                spec.extend_from_slice(&(bytes.min(u32::MAX.into()) as u32).to_ne_bytes());
+
+                // The queue holds what the rate drains within the latency.
+                let limit = bytes as u128 * latency.as_millis() / 1000 + u128::from(burst);
+                let limit = limit.min(u32::MAX.into()) as u32;
+
+                // struct tc_tbf_qopt: rate, peakrate, limit, buffer, mtu
+                let mut parms = spec;
+                parms.extend_from_slice(&[0u8; 12]);
+                parms.extend_from_slice(&limit.to_ne_bytes());
+                parms.extend_from_slice(&[0u8; 8]);
+
+                let mut options = vec![
+                    Attribute::new(TCA_TBF_PARMS, parms),
+                    Attribute::new(TCA_TBF_BURST, burst.to_ne_bytes().to_vec()),
+                ];
+                if bytes > u64::from(u32::MAX) {
+                    options.push(Attribute::new(TCA_TBF_RATE64, bytes.to_ne_bytes().to_vec()));
+                }
+
+                vec![
+                    Attribute::string(TCA_KIND, "tbf"),
+                    Attribute::nested(TCA_OPTIONS, &options),
+                ]
+            }
+
+            Self::FqCodel => vec![Attribute::string(TCA_KIND, "fq_codel")],
+        }
+    }
+}
+
+/// A request for a queueing discipline (struct tcmsg and its attributes)
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct QdiscMessage {
+    index: u32,
+    handle: u32,
+    parent: u32,
+    attributes: Vec<Attribute>,
+}
+
+impl NetlinkSerializable<QdiscMessage> for QdiscMessage {
+    fn message_type(&self) -> u16 {
+        RTM_NEWQDISC
+    }
+
+    fn buffer_len(&self) -> usize {
+        20 + encode(&self.attributes).len()
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) {
+        // family and padding, ifindex, handle, parent, info
+        buffer[..4].copy_from_slice(&[0u8; 4]);
+        buffer[4..8].copy_from_slice(&self.index.to_ne_bytes());
+        buffer[8..12].copy_from_slice(&self.handle.to_ne_bytes());
+        buffer[12..16].copy_from_slice(&self.parent.to_ne_bytes());
+        buffer[16..20].copy_from_slice(&[0u8; 4]);
+
+        let attributes = encode(&self.attributes);
+        buffer[20..20 + attributes.len()].copy_from_slice(&attributes);
+    }
+}
+
+impl From<QdiscMessage> for NetlinkPayload<QdiscMessage> {
+    #[inline]
+    fn from(value: QdiscMessage) -> Self {
+        NetlinkPayload::InnerMessage(value)
+    }
+}
+
+impl Interface {
+    /// Replaces the root queueing discipline
+    pub fn set_qdisc(&mut self, qdisc: &Qdisc) -> Result<(), Error> {
+        debug!("setting qdisc: interface={} qdisc={}", self, qdisc);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE,
+                ..Default::default()
+            },
+            payload: QdiscMessage {
+                index: self.index(),
+                handle: HANDLE,
+                parent: TC_H_ROOT,
+                attributes: qdisc.attributes(),
+            }
+            .into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(()),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::generic::decode;
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        let mut value = [0u8; 4];
+        value.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_ne_bytes(value)
+    }
+
+    /// Returns the kind and the options of a queueing discipline
+    fn options(qdisc: Qdisc) -> (String, Vec<Attribute>) {
+        let attributes = qdisc.attributes();
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes[0].kind(), TCA_KIND);
+        assert_eq!(attributes[1].kind(), TCA_OPTIONS);
+
+        let kind = attributes[0].as_str().unwrap().to_string();
+        (kind, attributes[1].attributes().unwrap())
+    }
+
+    #[test]
+    fn tbf() {
+        let (kind, options) = options(Qdisc::Tbf {
+            rate: 100_000_000,
+            burst: 32768,
+            latency: Duration::from_millis(50),
+        });
+        assert_eq!(kind, "tbf");
+        assert_eq!(options.len(), 2);
+
+        let parms = &options[0];
+        assert_eq!(parms.kind(), TCA_TBF_PARMS);
+        assert_eq!(parms.value().len(), 36);
+        assert_eq!(parms.value()[1], TC_LINKLAYER_ETHERNET);
+        assert_eq!(u32_at(parms.value(), 8), 12_500_000);
+        assert_eq!(u32_at(parms.value(), 24), 12_500_000 / 20 + 32768);
+
+        assert_eq!(options[1].kind(), TCA_TBF_BURST);
+        assert_eq!(options[1].as_u32(), Some(32768));
+    }
+
+    #[test]
+    fn tbf_rate64() {
+        let (_, options) = options(Qdisc::Tbf {
+            rate: 40_000_000_000,
+            burst: 1 << 20,
+            latency: Duration::from_millis(10),
+        });
+        assert_eq!(options.len(), 3);
+        assert_eq!(u32_at(options[0].value(), 8), u32::MAX);
+        assert_eq!(options[2].kind(), TCA_TBF_RATE64);
+        assert_eq!(options[2].as_u64(), Some(5_000_000_000));
+    }
+
+    #[test]
+    fn serialize() {
+        let msg = QdiscMessage {
+            index: 7,
+            handle: HANDLE,
+            parent: TC_H_ROOT,
+            attributes: Qdisc::FqCodel.attributes(),
+        };
+
+        let mut buffer = vec![0xffu8; msg.buffer_len()];
+        msg.serialize(&mut buffer);
+        assert_eq!(&buffer[..4], &[0u8; 4]);
+        assert_eq!(u32_at(&buffer, 4), 7);
+        assert_eq!(u32_at(&buffer, 8), HANDLE);
+        assert_eq!(u32_at(&buffer, 12), TC_H_ROOT);
+
+        let attributes = decode(&buffer[20..]).unwrap();
+        assert_eq!(attributes, [Attribute::string(TCA_KIND, "fq_codel")]);
+    }
+}
@@ -0,0 +1,426 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Connection, Error, Interface, Subnet};
+
+use log::debug;
+use netlink_packet_route::*;
+
+pub use netlink_packet_route::RT_TABLE_MAIN;
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+fn octets(address: IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(addr) => addr.octets().into(),
+        IpAddr::V6(addr) => addr.octets().into(),
+    }
+}
+
+fn address(family: u8, bytes: &[u8]) -> Option<IpAddr> {
+    match (u16::from(family), bytes.len()) {
+        (AF_INET, 4) => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::V4(octets.into()))
+        }
+
+        (AF_INET6, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::V6(octets.into()))
+        }
+
+        _ => None,
+    }
+}
+
+/// What happens to packets matching a route
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RouteKind {
+    /// They are forwarded (the usual case)
+    Unicast,
+
+    /// They are silently discarded
+    Blackhole,
+
+    /// They are discarded with an ICMP host unreachable error
+    Unreachable,
+
+    /// They are discarded with an ICMP administratively prohibited error
+    Prohibit,
+}
+
+impl std::fmt::Display for RouteKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unicast => write!(f, "unicast"),
+            Self::Blackhole => write!(f, "blackhole"),
+            Self::Unreachable => write!(f, "unreachable"),
+            Self::Prohibit => write!(f, "prohibit"),
+        }
+    }
+}
+
+impl FromStr for RouteKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unicast" => Ok(Self::Unicast),
+            "blackhole" => Ok(Self::Blackhole),
+            "unreachable" => Ok(Self::Unreachable),
+            "prohibit" => Ok(Self::Prohibit),
+            _ => Err(format!("invalid route kind: {}", s)),
+        }
+    }
+}
+
+impl RouteKind {
+    fn decode(kind: u8) -> Option<Self> {
+        match kind {
+            RTN_UNICAST => Some(Self::Unicast),
+            RTN_BLACKHOLE => Some(Self::Blackhole),
+            RTN_UNREACHABLE => Some(Self::Unreachable),
+            RTN_PROHIBIT => Some(Self::Prohibit),
+            _ => None,
+        }
+    }
+
+    fn encode(self) -> u8 {
+        match self {
+            Self::Unicast => RTN_UNICAST,
+            Self::Blackhole => RTN_BLACKHOLE,
+            Self::Unreachable => RTN_UNREACHABLE,
+            Self::Prohibit => RTN_PROHIBIT,
+        }
+    }
+}
+
+/// A unicast route, or one discarding the packets to its destination
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Route {
+    destination: Subnet,
+    gateway: Option<IpAddr>,
+    kind: RouteKind,
+
+    /// The index of the output interface (only known for listed routes)
+    interface: Option<u32>,
+
+    /// The routing table
+    table: u32,
+
+    /// The priority of the route (lower is preferred)
+    metric: Option<u32>,
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.kind, self.gateway) {
+            (RouteKind::Unicast, Some(gateway)) => write!(f, "{}@{}", self.destination, gateway),
+            (RouteKind::Unicast, None) => write!(f, "{}", self.destination),
+            (kind, _) => write!(f, "{}@{}", self.destination, kind),
+        }
+    }
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, '@');
+
+        let destination: Subnet = split
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|e| format!("{}: {}", s, e))?;
+
+        // Special routes name their kind instead of a gateway.
+        let via = split.next();
+        if let Some(kind) = via.and_then(|v| v.parse().ok()) {
+            return Ok(Self::special(destination, kind));
+        }
+
+        let gateway = match via {
+            Some(gateway) => Some(
+                gateway
+                    .parse::<IpAddr>()
+                    .map_err(|e| format!("{}: {}", s, e))?,
+            ),
+            None => None,
+        };
+
+        if gateway
+            .iter()
+            .any(|g| g.is_ipv4() != destination.address().is_ipv4())
+        {
+            return Err(format!("{}: address family mismatch", s));
+        }
+
+        Ok(Self::new(destination, gateway))
+    }
+}
+
+impl Route {
+    #[inline]
+    pub fn new(destination: Subnet, gateway: Option<IpAddr>) -> Self {
+        Self {
+            destination,
+            gateway,
+            kind: RouteKind::Unicast,
+            interface: None,
+            table: RT_TABLE_MAIN.into(),
+            metric: None,
+        }
+    }
+
+    /// Creates a route discarding the packets to `destination`
+    pub fn special(destination: Subnet, kind: RouteKind) -> Self {
+        Self {
+            kind,
+            ..Self::new(destination, None)
+        }
+    }
+
+    /// Creates a default route through `gateway`
+    pub fn default_via(gateway: IpAddr) -> Self {
+        let any = match gateway {
+            IpAddr::V4(..) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(..) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        Self::new(Subnet::new(any, 0), Some(gateway))
+    }
+
+    /// Places the route in another routing table
+    #[inline]
+    #[allow(dead_code)]
+    pub fn with_table(self, table: u32) -> Self {
+        Self { table, ..self }
+    }
+
+    /// Sets the priority of the route (or leaves it to the kernel)
+    #[inline]
+    pub fn with_metric(self, metric: Option<u32>) -> Self {
+        Self { metric, ..self }
+    }
+
+    /// Lists the unicast and special routes in all routing tables
+    pub fn list() -> Result<Vec<Self>, Error> {
+        debug!("listing routes");
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_DUMP,
+                ..Default::default()
+            },
+            payload: RtnlMessage::GetRoute(Default::default()).into(),
+        })?;
+
+        let mut routes = Vec::new();
+        loop {
+            let msg = match nl.pull()?.payload {
+                NetlinkPayload::Done => break Ok(routes),
+                NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(msg)) => msg,
+                _ => return Err(ErrorKind::InvalidData.into()),
+            };
+
+            let family = msg.header.address_family;
+            let kind = match RouteKind::decode(msg.header.kind) {
+                Some(kind) => kind,
+                None => continue,
+            };
+
+            let mut destination = None;
+            let mut gateway = None;
+            let mut interface = None;
+            let mut table = u32::from(msg.header.table);
+            let mut metric = None;
+            for nla in msg.nlas {
+                match nla {
+                    route::Nla::Destination(bytes) => destination = address(family, &bytes),
+                    route::Nla::Gateway(bytes) => gateway = address(family, &bytes),
+                    route::Nla::Oif(index) => interface = Some(index),
+                    route::Nla::Table(value) => table = value,
+                    route::Nla::Priority(value) => metric = Some(value),
+                    _ => (),
+                }
+            }
+
+            // Default routes have no destination.
+            let destination = match destination {
+                Some(destination) => destination,
+                None if u16::from(family) == AF_INET => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                None if u16::from(family) == AF_INET6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                None => continue,
+            };
+
+            routes.push(Self {
+                destination: Subnet::new(destination, msg.header.destination_prefix_length),
+                gateway,
+                kind,
+                interface,
+                table,
+                metric,
+            });
+        }
+    }
+
+    #[inline]
+    pub fn destination(&self) -> Subnet {
+        self.destination
+    }
+
+    #[inline]
+    pub fn gateway(&self) -> Option<IpAddr> {
+        self.gateway
+    }
+
+    #[inline]
+    pub fn kind(&self) -> RouteKind {
+        self.kind
+    }
+
+    /// Returns the index of the output interface (if known)
+    #[inline]
+    pub fn interface(&self) -> Option<u32> {
+        self.interface
+    }
+
+    #[inline]
+    pub fn table(&self) -> u32 {
+        self.table
+    }
+
+    #[inline]
+    pub fn metric(&self) -> Option<u32> {
+        self.metric
+    }
+
+    fn message(&self, interface: Option<u32>) -> RouteMessage {
+        let mut nlas = vec![
+            route::Nla::Destination(octets(self.destination.address())),
+            route::Nla::Table(self.table),
+        ];
+        if let Some(gateway) = self.gateway {
+            nlas.push(route::Nla::Gateway(octets(gateway)));
+        }
+        if let (RouteKind::Unicast, Some(index)) = (self.kind, interface) {
+            nlas.push(route::Nla::Oif(index));
+        }
+        if let Some(metric) = self.metric {
+            nlas.push(route::Nla::Priority(metric));
+        }
+
+        RouteMessage {
+            header: RouteHeader {
+                kind: self.kind.encode(),
+                address_family: match self.destination.address() {
+                    IpAddr::V4(..) => AF_INET as u8,
+                    IpAddr::V6(..) => AF_INET6 as u8,
+                },
+                destination_prefix_length: self.destination.prefix(),
+                table: RT_TABLE_UNSPEC,
+                scope: match (self.kind, self.gateway) {
+                    (RouteKind::Unicast, None) => RT_SCOPE_LINK,
+                    _ => RT_SCOPE_UNIVERSE,
+                },
+                ..Default::default()
+            },
+            nlas,
+        }
+    }
+
+    /// Adds the route through `interface`
+    ///
+    /// Without a gateway, the destination is considered directly reachable.
+    /// Special routes go through no interface, so `interface` only tells
+    /// which namespace they belong to.
+    pub fn add(&self, interface: &Interface) -> Result<(), Error> {
+        debug!("adding route: interface={} route={}", interface, self);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+                ..Default::default()
+            },
+            payload: RtnlMessage::NewRoute(self.message(Some(interface.index()))).into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(()),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Deletes the route
+    #[allow(dead_code)]
+    pub fn delete(&self) -> Result<(), Error> {
+        debug!("deleting route: route={} table={}", self, self.table);
+        let mut nl = Connection::new()?;
+        nl.push(NetlinkMessage {
+            header: NetlinkHeader {
+                flags: NLM_F_REQUEST | NLM_F_ACK,
+                ..Default::default()
+            },
+            payload: RtnlMessage::DelRoute(self.message(self.interface)).into(),
+        })?;
+
+        match nl.pull::<RtnlMessage>()?.payload {
+            NetlinkPayload::Ack(..) => Ok(()),
+            _ => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::ip;
+
+    fn subnet(s: &str) -> Subnet {
+        s.parse().ok().unwrap()
+    }
+
+    #[test]
+    fn from_str() {
+        let route: Route = "10.0.0.0/8@10.2.0.1".parse().unwrap();
+        assert_eq!(
+            route,
+            Route::new(subnet("10.0.0.0/8"), Some(ip("10.2.0.1")))
+        );
+        assert_eq!(route.kind(), RouteKind::Unicast);
+        assert_eq!(route.to_string(), "10.0.0.0/8@10.2.0.1");
+
+        let route: Route = "2001:db8::/32".parse().unwrap();
+        assert_eq!(route.gateway(), None);
+        assert_eq!(route.to_string(), "2001:db8::/32");
+
+        assert!("10.0.0.0/8@2001:db8::1".parse::<Route>().is_err());
+        assert!("10.0.0.0/8@gateway".parse::<Route>().is_err());
+        assert!("10.0.0.0@10.2.0.1".parse::<Route>().is_err());
+    }
+
+    #[test]
+    fn default_via() {
+        let route = Route::default_via(ip("2001:db8::1"));
+        assert_eq!(route.destination(), subnet("::/0"));
+        assert_eq!(route.gateway(), Some(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn special() {
+        for kind in &["blackhole", "unreachable", "prohibit"] {
+            let text = format!("10.0.0.0/8@{}", kind);
+            let route: Route = text.parse().unwrap();
+            assert_eq!(route.kind(), kind.parse().unwrap());
+            assert_eq!(route.gateway(), None);
+            assert_eq!(route.to_string(), text);
+        }
+
+        assert_eq!("unicast".parse::<RouteKind>(), Ok(RouteKind::Unicast));
+        assert!("discard".parse::<RouteKind>().is_err());
+    }
+}
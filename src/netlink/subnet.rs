@@ -2,7 +2,6 @@
 
 use std::net::IpAddr;
 use std::str::FromStr;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Subnet {
@@ -13,13 +12,25 @@ pub struct Subnet {
 pub enum Error {
     Address(std::net::AddrParseError),
     Prefix(std::num::ParseIntError),
+    Length(u8, &'static str),
     Field,
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Address(e) => write!(f, "invalid address: {}", e),
+            Self::Prefix(e) => write!(f, "invalid prefix: {}", e),
+            Self::Length(prefix, family) => write!(f, "invalid prefix '{}' for {}", prefix, family),
+            Self::Field => write!(f, "expected <address>/<prefix>"),
+        }
+    }
+}
+
 impl From<Error> for std::io::Error {
     #[inline]
-    fn from(_value: Error) -> Self {
-        std::io::ErrorKind::InvalidInput.into()
+    fn from(value: Error) -> Self {
+        Self::new(std::io::ErrorKind::InvalidInput, value.to_string())
     }
 }
 
@@ -54,23 +65,33 @@ impl FromStr for Subnet {
             return Err(Error::Field);
         }
 
-        Ok(Self::new(addr.parse()?, pfix.parse()?))
+        let addr: IpAddr = addr.parse()?;
+        let prefix: u8 = pfix.parse()?;
+        match addr {
+            IpAddr::V4(..) if prefix > 32 => Err(Error::Length(prefix, "IPv4")),
+            IpAddr::V6(..) if prefix > 128 => Err(Error::Length(prefix, "IPv6")),
+            _ => Ok(Self::new(addr, prefix)),
+        }
     }
 }
 
 impl Subnet {
+    /// Clears the host bits of `addr`
+    ///
+    /// Neither a zero prefix nor one longer than the address may panic, since
+    /// `new()` is also given prefixes reported by the kernel.
     fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
         match addr {
             IpAddr::V4(addr) => {
-                let shift = 32 - prefix;
-                let mask = !0 >> shift << shift;
+                let shift = u32::from(32u8.saturating_sub(prefix));
+                let mask = u32::MAX.checked_shl(shift).unwrap_or(0);
                 let addr = u32::from(addr) & mask;
                 addr.to_be_bytes().into()
             }
 
             IpAddr::V6(addr) => {
-                let shift = 128 - prefix;
-                let mask = !0 >> shift << shift;
+                let shift = u32::from(128u8.saturating_sub(prefix));
+                let mask = u128::MAX.checked_shl(shift).unwrap_or(0);
                 let addr = u128::from(addr) & mask;
                 addr.to_be_bytes().into()
             }
@@ -95,25 +116,50 @@ impl Subnet {
         self.prefix
     }
 
-    pub fn random(&self) -> IpAddr {
-        let rand = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
+    /// Returns the address within the subnet whose host bits are taken
+    /// from `random`
+    pub fn random(&self, random: u128) -> IpAddr {
+        let shift = u32::from(self.prefix);
 
         match self.address() {
             IpAddr::V4(addr) => {
-                let rand = (rand as u32) << self.prefix >> self.prefix;
-                (u32::from(addr) | rand).to_be_bytes().into()
+                let host = u32::MAX.checked_shr(shift).unwrap_or(0);
+                (u32::from(addr) | (random as u32 & host))
+                    .to_be_bytes()
+                    .into()
             }
 
             IpAddr::V6(addr) => {
-                let rand = rand << self.prefix >> self.prefix;
-                (u128::from(addr) | rand).to_be_bytes().into()
+                let host = u128::MAX.checked_shr(shift).unwrap_or(0);
+                (u128::from(addr) | (random & host)).to_be_bytes().into()
             }
         }
     }
 
+    /// Returns the last address within the subnet (the IPv4 broadcast address)
+    #[inline]
+    pub fn last(&self) -> IpAddr {
+        self.random(u128::MAX)
+    }
+
+    /// Whether `addr` may be assigned to an interface
+    ///
+    /// This excludes the network address (the IPv6 subnet-router anycast
+    /// address) and the IPv4 broadcast address, except in point-to-point
+    /// (/31) and host (/32) subnets, which have no room for them.
+    pub fn assignable(&self, addr: IpAddr) -> bool {
+        if !self.contains(addr) {
+            return false;
+        }
+
+        match addr {
+            IpAddr::V4(..) if self.prefix >= 31 => true,
+            IpAddr::V4(..) => addr != self.address && addr != self.last(),
+            IpAddr::V6(..) if self.prefix == 128 => true,
+            IpAddr::V6(..) => addr != self.address,
+        }
+    }
+
     #[inline]
     pub fn contains(&self, addr: IpAddr) -> bool {
         match (self.address, addr) {
@@ -125,3 +171,85 @@ impl Subnet {
         Self::mask(addr, self.prefix) == self.address
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::ip;
+
+    #[test]
+    fn new_masks_host_bits() {
+        let subnet = Subnet::new(ip("10.1.2.3"), 16);
+        assert_eq!(subnet.address(), ip("10.1.0.0"));
+        assert_eq!(subnet.prefix(), 16);
+
+        let subnet = Subnet::new(ip("2001:db8::1"), 32);
+        assert_eq!(subnet.address(), ip("2001:db8::"));
+    }
+
+    #[test]
+    fn new_zero_prefix() {
+        let any = Subnet::new(ip("10.1.2.3"), 0);
+        assert_eq!(any.address(), ip("0.0.0.0"));
+        assert!(any.contains(ip("192.0.2.1")));
+        assert!(!any.contains(ip("2001:db8::1")));
+
+        let any = Subnet::new(ip("2001:db8::1"), 0);
+        assert_eq!(any.address(), ip("::"));
+        assert!(any.contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn new_full_prefix() {
+        assert_eq!(Subnet::new(ip("10.1.2.3"), 32).address(), ip("10.1.2.3"));
+        assert_eq!(
+            Subnet::new(ip("2001:db8::1"), 128).address(),
+            ip("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn new_long_prefix() {
+        assert_eq!(Subnet::new(ip("10.1.2.3"), 40).address(), ip("10.1.2.3"));
+    }
+
+    #[test]
+    fn from_str() {
+        let subnet: Subnet = "10.1.2.3/24".parse().ok().unwrap();
+        assert_eq!(subnet, Subnet::new(ip("10.1.2.0"), 24));
+        assert_eq!(subnet.to_string(), "10.1.2.0/24");
+
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert!("2001:db8::/129".parse::<Subnet>().is_err());
+        assert!("10.0.0.0".parse::<Subnet>().is_err());
+        assert!("10.0.0.0/8/8".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn last() {
+        assert_eq!(Subnet::new(ip("10.0.0.0"), 24).last(), ip("10.0.0.255"));
+        assert_eq!(Subnet::new(ip("10.0.0.1"), 32).last(), ip("10.0.0.1"));
+        assert_eq!(Subnet::new(ip("10.0.0.0"), 0).last(), ip("255.255.255.255"));
+        assert_eq!(
+            Subnet::new(ip("2001:db8::"), 64).last(),
+            ip("2001:db8::ffff:ffff:ffff:ffff")
+        );
+    }
+
+    #[test]
+    fn assignable() {
+        let subnet = Subnet::new(ip("10.0.0.0"), 24);
+        assert!(!subnet.assignable(ip("10.0.0.0")));
+        assert!(!subnet.assignable(ip("10.0.0.255")));
+        assert!(subnet.assignable(ip("10.0.0.1")));
+        assert!(!subnet.assignable(ip("10.0.1.1")));
+
+        let p2p = Subnet::new(ip("10.0.0.0"), 31);
+        assert!(p2p.assignable(ip("10.0.0.0")));
+        assert!(p2p.assignable(ip("10.0.0.1")));
+
+        let subnet = Subnet::new(ip("2001:db8::"), 64);
+        assert!(!subnet.assignable(ip("2001:db8::")));
+        assert!(subnet.assignable(ip("2001:db8::ffff")));
+    }
+}
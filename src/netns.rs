@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Persistent named network namespaces compatible with `ip netns`
+
+use std::ffi::CString;
+use std::fs::{read_dir, remove_file, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+
+use caps::Capability;
+
+/// The directory where `ip netns` keeps its namespace mounts
+pub const NETNS_DIR: &str = "/var/run/netns";
+
+fn cstring(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| ErrorKind::InvalidInput.into())
+}
+
+/// Returns the mount point for a named namespace
+pub fn path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid namespace name: {}", name),
+        ));
+    }
+
+    Ok(Path::new(NETNS_DIR).join(name))
+}
+
+/// Returns the mount points of all named namespaces
+pub fn list() -> Result<Vec<PathBuf>> {
+    match read_dir(NETNS_DIR) {
+        Ok(dir) => Ok(dir.filter_map(|e| e.ok().map(|e| e.path())).collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates an empty mount point for a named namespace
+pub fn create(name: &str) -> Result<PathBuf> {
+    let path = path(name)?;
+
+    // The mount target must not be replaceable before it is mounted over.
+    crate::state::directory(Path::new(NETNS_DIR))?;
+    let file = caps::with(Capability::CAP_DAC_OVERRIDE, || {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o0444)
+            .open(&path)
+    })?;
+    crate::state::chown(&file)?;
+
+    Ok(path)
+}
+
+/// Bind mounts the namespace `ns` onto the mount point `path`
+pub fn bind(ns: &impl AsRawFd, path: &Path) -> Result<()> {
+    let source = cstring(&Path::new("/proc/self/fd").join(ns.as_raw_fd().to_string()))?;
+    let target = cstring(path)?;
+    let fstype = CString::new("none").unwrap();
+
+    caps::with(Capability::CAP_SYS_ADMIN, || {
+        match unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                fstype.as_ptr(),
+                libc::MS_BIND,
+                std::ptr::null(),
+            )
+        } {
+            -1 => Err(Error::last_os_error()),
+            0 => Ok(()),
+            _ => unreachable!(),
+        }
+    })
+}
+
+/// Unmounts (if mounted) and removes the mount point `path`
+pub fn remove(path: &Path) -> Result<()> {
+    let target = cstring(path)?;
+
+    caps::with(Capability::CAP_SYS_ADMIN, || {
+        match unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } {
+            -1 => match Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::EINVAL) => Ok(()),
+                e => Err(e),
+            },
+            0 => Ok(()),
+            _ => unreachable!(),
+        }
+    })?;
+
+    caps::with(Capability::CAP_DAC_OVERRIDE, || remove_file(path))
+}
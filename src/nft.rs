@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Loading an nftables ruleset within the namespace
+//!
+//! The ruleset is piped to `nft`, which is given `CAP_NET_ADMIN` as an
+//! ambient capability since it doesn't keep ours across `execve()`.
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use caps::{CapSet, Capability};
+use log::debug;
+
+/// The nftables utility (never looked up in the caller's `PATH`)
+const NFT: &str = "/usr/sbin/nft";
+
+/// An nftables ruleset from the configuration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ruleset {
+    /// A file containing the ruleset
+    File(PathBuf),
+
+    /// The ruleset itself
+    Inline(String),
+}
+
+impl std::fmt::Display for Ruleset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Inline(..) => write!(f, "(inline)"),
+        }
+    }
+}
+
+impl Ruleset {
+    /// Reads the ruleset, validating its file like a configuration file
+    pub fn read(&self) -> Result<String> {
+        match self {
+            Self::Inline(ruleset) => Ok(ruleset.clone()),
+            Self::File(path) => {
+                let mut ruleset = String::new();
+                crate::config::open(path, None)?.read_to_string(&mut ruleset)?;
+                Ok(ruleset)
+            }
+        }
+    }
+}
+
+/// Loads `ruleset` into the current network namespace
+pub fn load(ruleset: &str) -> Result<()> {
+    debug!("loading nftables ruleset: bytes={}", ruleset.len());
+
+    let mut command = Command::new(NFT);
+    command
+        .args(&["-f", "-"])
+        .env_clear()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null());
+
+    unsafe {
+        command.pre_exec(|| {
+            caps::raise(None, CapSet::Inheritable, Capability::CAP_NET_ADMIN)?;
+            caps::raise(None, CapSet::Ambient, Capability::CAP_NET_ADMIN)?;
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(ruleset.as_bytes())?;
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("nft failed: {}", status),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Restricting the programs executed in a namespace
+//!
+//! Once a program is allowed, the very file which was checked is what gets
+//! executed (through `/proc/self/fd`), so the check can't be sidestepped
+//! through `PATH` or by replacing the file afterwards. The file stays open in
+//! the program, since the interpreter of a script reopens it by that path.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A program allowed by the configuration
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Program {
+    /// An absolute path to the program
+    Path(PathBuf),
+
+    /// The (lowercase hex) SHA-256 digest of the program
+    Sha256(String),
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Sha256(digest) => write!(f, "sha256:{}", digest),
+        }
+    }
+}
+
+impl FromStr for Program {
+    type Err = String;
+
+    /// Parses an absolute path or `sha256:DIGEST`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(digest) = s.strip_prefix("sha256:") {
+            if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!("invalid sha256 digest: {}", digest));
+            }
+
+            return Ok(Self::Sha256(digest.to_ascii_lowercase()));
+        }
+
+        if !s.starts_with('/') {
+            return Err(format!("program must be an absolute path: {}", s));
+        }
+
+        Ok(Self::Path(s.into()))
+    }
+}
+
+/// Finds the program `argv0` executes, searching `PATH` like `execvp()`
+fn resolve(argv0: &str) -> Result<PathBuf> {
+    if argv0.contains('/') {
+        return Path::new(argv0).canonicalize();
+    }
+
+    let path = std::env::var_os("PATH").unwrap_or_else(|| "/usr/bin:/bin".into());
+    for dir in std::env::split_paths(&path) {
+        let candidate = dir.join(argv0);
+        if let Ok(md) = candidate.metadata() {
+            if md.is_file() && md.mode() & 0o111 != 0 {
+                return candidate.canonicalize();
+            }
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!("{}: command not found", argv0),
+    ))
+}
+
+/// Returns the (lowercase hex) SHA-256 digest of everything `reader` reads
+pub fn sha256(mut reader: impl Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// A program to execute
+#[derive(Debug, Default)]
+pub struct Executable {
+    path: PathBuf,
+
+    /// The file which was checked, if any
+    file: Option<File>,
+}
+
+impl Executable {
+    /// The path to execute, referring to the checked file (if any) itself
+    fn path(&self) -> PathBuf {
+        match &self.file {
+            Some(file) => format!("/proc/self/fd/{}", file.as_raw_fd()).into(),
+            None => self.path.clone(),
+        }
+    }
+
+    /// Returns a command executing the program
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(self.path());
+
+        if let Some(file) = &self.file {
+            let fd = file.as_raw_fd();
+            unsafe {
+                command.pre_exec(move || match libc::fcntl(fd, libc::F_SETFD, 0) {
+                    -1 => Err(Error::last_os_error()),
+                    _ => Ok(()),
+                });
+            }
+        }
+
+        command
+    }
+
+    /// Whether the program was checked against the allowed programs
+    pub fn checked(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+/// Copies the program in `original` into a sealed memory file
+///
+/// The digest of the copy is returned with it, since the copy (unlike the
+/// original) can't change after it has been hashed.
+fn seal(mut original: &File) -> Result<(File, String)> {
+    let name = b"ipvlan\0";
+    let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+    let mut copy = match unsafe { libc::memfd_create(name.as_ptr() as _, flags) } {
+        -1 => return Err(Error::last_os_error()),
+        fd => unsafe { File::from_raw_fd(fd) },
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let size = original.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..size]);
+        copy.write_all(&buffer[..size])?;
+    }
+
+    let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+    match unsafe { libc::fcntl(copy.as_raw_fd(), libc::F_ADD_SEALS, seals) } {
+        -1 => return Err(Error::last_os_error()),
+        0 => (),
+        _ => unreachable!(),
+    }
+
+    copy.seek(SeekFrom::Start(0))?;
+    Ok((copy, format!("{:x}", hasher.finalize())))
+}
+
+/// Checks that `argv` runs an allowed program and returns the one to execute
+///
+/// Everything is allowed if `allowed` is empty.
+pub fn check(allowed: &[Program], argv: &[String]) -> Result<Executable> {
+    if allowed.is_empty() {
+        return Ok(Executable {
+            path: argv[0].clone().into(),
+            file: None,
+        });
+    }
+
+    let path = resolve(&argv[0])?;
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_CLOEXEC)
+        .open(&path)?;
+    let md = file.metadata()?;
+
+    let mut sealed = None;
+    for program in allowed {
+        let matched = match program {
+            Program::Path(allowed) => match allowed.metadata() {
+                Ok(allowed) => (allowed.dev(), allowed.ino()) == (md.dev(), md.ino()),
+                Err(..) => false,
+            },
+
+            Program::Sha256(allowed) => {
+                if sealed.is_none() {
+                    sealed = Some(seal(&file)?);
+                }
+
+                sealed.as_ref().map(|(_, digest)| digest) == Some(allowed)
+            }
+        };
+
+        if matched {
+            debug!("program allowed: path={} by={}", path.display(), program);
+            let file = match (program, sealed) {
+                (Program::Sha256(..), Some((copy, _))) => copy,
+                _ => file,
+            };
+
+            return Ok(Executable {
+                path,
+                file: Some(file),
+            });
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::PermissionDenied,
+        format!("{} is not an allowed program", path.display()),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    /// Runs a script through `check()` with `allowed` computed from its path
+    fn run(name: &str, allowed: impl Fn(&Path) -> Program) -> String {
+        let dir = std::env::temp_dir().join(format!("ipvlan-{}-{}", name, std::process::id()));
+        create_dir_all(&dir).unwrap();
+
+        let script = dir.join("script");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .mode(0o755)
+            .open(&script)
+            .unwrap();
+        file.write_all(b"#!/bin/sh\necho \"$@\"\n").unwrap();
+        drop(file);
+
+        let argv = [script.to_str().unwrap().to_string(), "hello".into()];
+        let executable = check(&[allowed(&script)], &argv).unwrap();
+        assert!(executable.checked());
+
+        let output = executable.command().args(&argv[1..]).output().unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn script_by_path() {
+        let output = run("path", |p| Program::Path(p.to_path_buf()));
+        assert_eq!(output, "hello\n");
+    }
+
+    #[test]
+    fn script_by_digest() {
+        let output = run("sha256", |p| {
+            Program::Sha256(sha256(File::open(p).unwrap()).unwrap())
+        });
+        assert_eq!(output, "hello\n");
+    }
+}
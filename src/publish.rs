@@ -0,0 +1,277 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Forwarding of host ports into the namespace
+//!
+//! The listening sockets are bound while still in the host namespace. The
+//! forwarding threads are spawned after switching namespaces, so the
+//! connections they make to the command originate within the new namespace.
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::{ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread::spawn;
+
+use log::{debug, info, warn};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp"),
+            Self::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "tcp" => Self::Tcp,
+            "udp" => Self::Udp,
+            _ => return Err(format!("invalid protocol: {}", s)),
+        })
+    }
+}
+
+/// A host port published to a port in the namespace
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Publish {
+    pub host: u16,
+    pub port: u16,
+    pub protocol: Protocol,
+}
+
+impl std::fmt::Display for Publish {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}/{}", self.host, self.port, self.protocol)
+    }
+}
+
+impl FromStr for Publish {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid port mapping: {}", s);
+
+        let mut split = s.splitn(2, '/');
+        let ports = split.next().unwrap_or_default();
+        let protocol = match split.next() {
+            Some(protocol) => protocol.parse()?,
+            None => Protocol::Tcp,
+        };
+
+        let mut split = ports.splitn(2, ':');
+        match (split.next(), split.next()) {
+            (Some(host), Some(port)) => Ok(Self {
+                host: host.parse().map_err(|_| invalid())?,
+                port: port.parse().map_err(|_| invalid())?,
+                protocol,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A socket listening on a published host port
+pub enum Listener {
+    Tcp(TcpListener, u16),
+    Udp(UdpSocket, u16),
+}
+
+impl Publish {
+    /// Binds the host port in the current namespace
+    pub fn listen(&self) -> Result<Listener> {
+        info!("publishing port: publish={}", self);
+        let addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), self.host);
+        Ok(match self.protocol {
+            Protocol::Tcp => Listener::Tcp(TcpListener::bind(addr)?, self.port),
+            Protocol::Udp => Listener::Udp(UdpSocket::bind(addr)?, self.port),
+        })
+    }
+}
+
+impl Listener {
+    /// Forwards traffic to the published port of `target` in the background
+    pub fn forward(self, target: IpAddr) {
+        match self {
+            Self::Tcp(listener, port) => {
+                spawn(move || tcp(listener, SocketAddr::new(target, port)));
+            }
+
+            Self::Udp(socket, port) => {
+                spawn(move || {
+                    if let Err(error) = udp(socket, SocketAddr::new(target, port)) {
+                        warn!("udp forwarding failed: error={}", error);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Copies one direction of a forwarded connection
+fn pipe(mut from: TcpStream, mut to: TcpStream) {
+    let _ = std::io::copy(&mut from, &mut to);
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+fn tcp(listener: TcpListener, target: SocketAddr) {
+    for client in listener.incoming() {
+        let result = client.and_then(|client| {
+            debug!("forwarding connection: peer={:?}", client.peer_addr());
+            let server = TcpStream::connect(target)?;
+            let (c, s) = (client.try_clone()?, server.try_clone()?);
+            spawn(move || pipe(c, s));
+            spawn(move || pipe(server, client));
+            Ok(())
+        });
+
+        if let Err(error) = result {
+            warn!("tcp forwarding failed: target={} error={}", target, error);
+        }
+    }
+}
+
+/// Relays datagrams, using one socket in the namespace per peer
+///
+/// Peers are never forgotten; this is meant for a small number of clients.
+fn udp(socket: UdpSocket, target: SocketAddr) -> Result<()> {
+    let socket = Arc::new(socket);
+    let mut peers = HashMap::<SocketAddr, UdpSocket>::new();
+    let mut buffer = vec![0u8; u16::MAX as usize];
+
+    loop {
+        let (len, peer) = socket.recv_from(&mut buffer)?;
+
+        let inner = match peers.entry(peer) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                debug!("forwarding datagrams: peer={}", peer);
+                let local: IpAddr = match target {
+                    SocketAddr::V4(..) => Ipv4Addr::UNSPECIFIED.into(),
+                    SocketAddr::V6(..) => Ipv6Addr::UNSPECIFIED.into(),
+                };
+
+                let inner = UdpSocket::bind(SocketAddr::new(local, 0))?;
+                inner.connect(target)?;
+
+                let (replies, socket) = (inner.try_clone()?, socket.clone());
+                spawn(move || {
+                    let mut buffer = vec![0u8; u16::MAX as usize];
+                    while let Ok(len) = replies.recv(&mut buffer) {
+                        let _ = socket.send_to(&buffer[..len], peer);
+                    }
+                });
+
+                entry.insert(inner)
+            }
+        };
+
+        // Nothing may be listening yet; that's the peer's problem.
+        if let Err(error) = inner.send(&buffer[..len]) {
+            if error.kind() != ErrorKind::ConnectionRefused {
+                return Err(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::ip;
+
+    use std::io::{Read, Write};
+
+    fn publish(port: u16, protocol: Protocol) -> Publish {
+        Publish {
+            host: 0,
+            port,
+            protocol,
+        }
+    }
+
+    #[test]
+    fn from_str() {
+        let publish: Publish = "8080:80".parse().unwrap();
+        assert_eq!(publish.host, 8080);
+        assert_eq!(publish.port, 80);
+        assert_eq!(publish.protocol, Protocol::Tcp);
+        assert_eq!(publish.to_string(), "8080:80/tcp");
+
+        let publish: Publish = "5353:53/udp".parse().unwrap();
+        assert_eq!(publish.protocol, Protocol::Udp);
+        assert_eq!(publish.to_string(), "5353:53/udp");
+
+        for invalid in &["80", "80:", ":80", "8080:80/sctp", "65536:80", "a:80"] {
+            assert!(invalid.parse::<Publish>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn tcp() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let host = match publish(port, Protocol::Tcp).listen().unwrap() {
+            Listener::Tcp(listener, port) => {
+                let host = listener.local_addr().unwrap().port();
+                Listener::Tcp(listener, port).forward(ip("127.0.0.1"));
+                host
+            }
+            Listener::Udp(..) => unreachable!(),
+        };
+
+        let mut client = TcpStream::connect(("127.0.0.1", host)).unwrap();
+        client.write_all(b"ping").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let (mut stream, _) = server.accept().unwrap();
+        let mut request = Vec::new();
+        stream.read_to_end(&mut request).unwrap();
+        assert_eq!(request, b"ping");
+        stream.write_all(b"pong").unwrap();
+        drop(stream);
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"pong");
+    }
+
+    #[test]
+    fn udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let host = match publish(port, Protocol::Udp).listen().unwrap() {
+            Listener::Udp(socket, port) => {
+                let host = socket.local_addr().unwrap().port();
+                Listener::Udp(socket, port).forward(ip("127.0.0.1"));
+                host
+            }
+            Listener::Tcp(..) => unreachable!(),
+        };
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(("127.0.0.1", host)).unwrap();
+        client.send(b"ping").unwrap();
+
+        let mut buffer = [0u8; 16];
+        let (len, peer) = server.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"ping");
+        server.send_to(b"pong", peer).unwrap();
+
+        let len = client.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"pong");
+    }
+}
@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A small, seedable pseudo-random number generator (SplitMix64)
+//!
+//! This is used to choose addresses, not for anything secret.
+
+use std::fs::File;
+use std::io::{Read, Result};
+
+pub struct Random(u64);
+
+impl Random {
+    /// Creates a generator producing the same sequence for the same seed
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Creates a generator seeded by the kernel
+    pub fn from_entropy() -> Result<Self> {
+        let mut seed = [0u8; 8];
+        File::open("/dev/urandom")?.read_exact(&mut seed)?;
+        Ok(Self(u64::from_ne_bytes(seed)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u128(&mut self) -> u128 {
+        ((self.next_u64() as u128) << 64) | self.next_u64() as u128
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seeded() {
+        let (mut a, mut b) = (Random::new(42), Random::new(42));
+        let a: Vec<u128> = (0..4).map(|_| a.next_u128()).collect();
+        let b: Vec<u128> = (0..4).map(|_| b.next_u128()).collect();
+        assert_eq!(a, b);
+
+        let mut c = Random::new(43);
+        assert_ne!(a[0], c.next_u128());
+    }
+}
@@ -0,0 +1,175 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Inclusive ranges of addresses
+
+use crate::netlink::Subnet;
+
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An inclusive range of addresses of a single family
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Range {
+    start: IpAddr,
+    end: IpAddr,
+}
+
+impl std::fmt::Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl FromStr for Range {
+    type Err = String;
+
+    /// Parses `START-END` or a single address
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid range: {}", s);
+
+        let mut split = s.splitn(2, '-');
+        let start: IpAddr = split
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| invalid())?;
+        let end: IpAddr = match split.next() {
+            Some(end) => end.parse().map_err(|_| invalid())?,
+            None => start,
+        };
+
+        if start.is_ipv4() != end.is_ipv4() || start > end {
+            return Err(invalid());
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+impl From<Subnet> for Range {
+    /// The range of every address within the subnet
+    fn from(subnet: Subnet) -> Self {
+        Self {
+            start: subnet.address(),
+            end: subnet.last(),
+        }
+    }
+}
+
+impl Range {
+    #[inline]
+    pub fn start(&self) -> IpAddr {
+        self.start
+    }
+
+    #[inline]
+    pub fn end(&self) -> IpAddr {
+        self.end
+    }
+
+    /// Returns the number of addresses in the range (saturating)
+    pub fn size(&self) -> u128 {
+        let offset = match (self.start, self.end) {
+            (IpAddr::V4(start), IpAddr::V4(end)) => u128::from(u32::from(end) - u32::from(start)),
+            (IpAddr::V6(start), IpAddr::V6(end)) => u128::from(end) - u128::from(start),
+            _ => unreachable!(),
+        };
+
+        offset.saturating_add(1)
+    }
+
+    /// Returns the address within the range whose offset from the start is
+    /// taken from `random`
+    pub fn random(&self, random: u128) -> IpAddr {
+        match (self.start, self.end) {
+            (IpAddr::V4(start), IpAddr::V4(end)) => {
+                let (start, end) = (u32::from(start), u32::from(end));
+                let size = u128::from(end - start) + 1;
+                (start + (random % size) as u32).to_be_bytes().into()
+            }
+
+            (IpAddr::V6(start), IpAddr::V6(end)) => {
+                let (start, end) = (u128::from(start), u128::from(end));
+                match (end - start).checked_add(1) {
+                    Some(size) => (start + random % size).to_be_bytes().into(),
+                    None => random.to_be_bytes().into(),
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        addr.is_ipv4() == self.start.is_ipv4() && self.start <= addr && addr <= self.end
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::ip;
+
+    #[test]
+    fn from_str() {
+        let range: Range = "10.0.0.10-10.0.0.19".parse().unwrap();
+        assert_eq!(range.start(), ip("10.0.0.10"));
+        assert_eq!(range.end(), ip("10.0.0.19"));
+        assert_eq!(range.to_string(), "10.0.0.10-10.0.0.19");
+
+        let single: Range = "10.0.0.10".parse().unwrap();
+        assert_eq!(single.start(), single.end());
+        assert_eq!(single.to_string(), "10.0.0.10");
+
+        assert!("10.0.0.19-10.0.0.10".parse::<Range>().is_err());
+        assert!("10.0.0.1-2001:db8::1".parse::<Range>().is_err());
+        assert!("10.0.0.1-".parse::<Range>().is_err());
+    }
+
+    #[test]
+    fn size() {
+        let range: Range = "10.0.0.10-10.0.0.19".parse().unwrap();
+        assert_eq!(range.size(), 10);
+
+        let all = Range::from(Subnet::new(ip("0.0.0.0"), 0));
+        assert_eq!(all.size(), 1u128 << 32);
+
+        let all = Range::from(Subnet::new(ip("::"), 0));
+        assert_eq!(all.size(), u128::MAX);
+    }
+
+    #[test]
+    fn random() {
+        let range: Range = "10.0.0.10-10.0.0.19".parse().unwrap();
+        assert_eq!(range.random(0), ip("10.0.0.10"));
+        assert_eq!(range.random(9), ip("10.0.0.19"));
+        assert_eq!(range.random(10), ip("10.0.0.10"));
+
+        let all = Range::from(Subnet::new(ip("::"), 0));
+        assert_eq!(
+            all.random(u128::MAX),
+            ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")
+        );
+    }
+
+    #[test]
+    fn contains() {
+        let range: Range = "10.0.0.10-10.0.0.19".parse().unwrap();
+        assert!(range.contains(ip("10.0.0.10")));
+        assert!(range.contains(ip("10.0.0.19")));
+        assert!(!range.contains(ip("10.0.0.20")));
+        assert!(!range.contains(ip("::a00:a")));
+    }
+
+    #[test]
+    fn from_subnet() {
+        let range = Range::from(Subnet::new(ip("10.0.0.0"), 24));
+        assert_eq!(range.start(), ip("10.0.0.0"));
+        assert_eq!(range.end(), ip("10.0.0.255"));
+    }
+}
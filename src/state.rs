@@ -0,0 +1,357 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Records of the namespaces created by ipvlan
+
+use crate::error::Error::Insecure;
+use crate::program::Program;
+
+use std::collections::BTreeMap;
+use std::fs::{read_dir, remove_file, rename, DirBuilder, File, Metadata, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::net::IpAddr;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use caps::{CapSet, Capability};
+use serde::{Deserialize, Serialize};
+
+/// The directory where records are kept
+pub const STATE_DIR: &str = "/run/ipvlan";
+
+/// An address assigned to an interface in a recorded namespace
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Assignment {
+    pub address: IpAddr,
+    pub prefix: u8,
+    pub gateway: IpAddr,
+}
+
+/// An interface created in a recorded namespace
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Link {
+    pub name: String,
+    pub addresses: Vec<Assignment>,
+}
+
+/// The record of a namespace created by ipvlan
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// The name of a persistent namespace (see `--name`)
+    pub name: Option<String>,
+
+    /// The real uid of the user who created the namespace
+    pub uid: u32,
+
+    /// The pid of the process executed in the namespace
+    pub pid: u32,
+
+    /// The command executed in the namespace
+    pub argv: Vec<String>,
+
+    /// The device and inode numbers identifying the namespace
+    pub namespace: (u64, u64),
+
+    /// The interfaces created in the namespace
+    pub links: Vec<Link>,
+
+    /// Arbitrary metadata given by the user (see `--label`)
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    /// The programs which may be executed in the namespace (or any if empty)
+    #[serde(default)]
+    pub programs: Vec<Program>,
+
+    /// The only command which may be executed in the namespace, except by
+    /// root, if it was locked (see `lock-command`)
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+}
+
+/// Whether the files we create are given to root (see `chown()`)
+///
+/// Without `CAP_CHOWN` they belong to whoever runs us, so their owner isn't
+/// checked (as before it was required).
+fn chowning() -> bool {
+    caps::has_cap(None, CapSet::Permitted, Capability::CAP_CHOWN).unwrap_or(false)
+}
+
+/// Whether only root could have written to a file (or directory)
+fn trusted(metadata: &Metadata) -> bool {
+    (metadata.uid() == 0 || !chowning()) && metadata.mode() & 0o022 == 0
+}
+
+/// Checks that only root could have written to `path`
+fn check(path: &Path, metadata: &Metadata) -> Result<()> {
+    if !trusted(metadata) {
+        return Err(Insecure(path.into(), "must be owned and only writable by root").into());
+    }
+
+    Ok(())
+}
+
+/// Opens a file, refusing it unless only root could have written it
+pub fn open(path: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    check(path, &file.metadata()?)?;
+    Ok(file)
+}
+
+/// Gives `file` to root (if `CAP_CHOWN` is permitted)
+///
+/// Our privileges come from file capabilities, so the files we create belong
+/// to the invoking user, who could otherwise forge their contents.
+pub fn chown(file: &File) -> Result<()> {
+    if !chowning() {
+        return Ok(());
+    }
+
+    caps::with(Capability::CAP_CHOWN, || {
+        match unsafe { libc::fchown(file.as_raw_fd(), 0, 0) } {
+            -1 => Err(Error::last_os_error()),
+            0 => Ok(()),
+            _ => unreachable!(),
+        }
+    })
+}
+
+/// Creates the directory `path` (if needed) for root, refusing an existing
+/// one which anyone else could have tampered with
+pub fn directory(path: &Path) -> Result<()> {
+    let created = caps::with(Capability::CAP_DAC_OVERRIDE, || {
+        match DirBuilder::new().mode(0o0755).create(path) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            result => result.map(|_| true),
+        }
+    })?;
+
+    let dir = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW)
+        .open(path)?;
+    if created {
+        chown(&dir)?;
+    }
+
+    check(path, &dir.metadata()?)
+}
+
+/// Atomically replaces the file at `path` (owned by root) with the contents
+/// written by `write`
+///
+/// The replacement never follows a link planted at `path`.
+pub fn replace(path: &Path, write: impl FnOnce(&File) -> Result<()>) -> Result<()> {
+    let name = path.file_name().ok_or(ErrorKind::InvalidInput)?;
+    let temporary = path.with_file_name(format!(
+        ".{}.{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let file = caps::with(Capability::CAP_DAC_OVERRIDE, || {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o0644)
+            .open(&temporary)
+    })?;
+
+    let result = chown(&file)
+        .and_then(|_| write(&file))
+        .and_then(|_| file.sync_all())
+        .and_then(|_| caps::with(Capability::CAP_DAC_OVERRIDE, || rename(&temporary, path)));
+    if result.is_err() {
+        let _ = caps::with(Capability::CAP_DAC_OVERRIDE, || remove_file(&temporary));
+    }
+    result
+}
+
+/// Locks the state directory to serialize invocations
+pub fn lock(timeout: Option<Duration>) -> Result<File> {
+    directory(Path::new(STATE_DIR))?;
+    let dir = File::open(STATE_DIR)?;
+    crate::lock(&dir, timeout)?;
+    Ok(dir)
+}
+
+fn named(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(ErrorKind::InvalidInput.into());
+    }
+
+    Ok(Path::new(STATE_DIR).join(format!("netns-{}", name)))
+}
+
+impl Record {
+    /// The path of the file storing this record
+    pub fn path(&self) -> Result<PathBuf> {
+        match &self.name {
+            Some(name) => named(name),
+            None => Ok(Path::new(STATE_DIR).join(format!("pid-{}", self.pid))),
+        }
+    }
+
+    /// Reads a record, refusing any which root didn't write
+    fn read(path: &Path) -> Result<Self> {
+        let dir = Path::new(STATE_DIR);
+        check(dir, &dir.symlink_metadata()?)?;
+
+        let file = open(path)?;
+        serde_json::from_reader(file).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Loads the record for the named namespace
+    pub fn load(name: &str) -> Result<Self> {
+        match Self::read(&named(name)?) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} was not created by ipvlan", name),
+            )),
+            result => result,
+        }
+    }
+
+    /// Loads the record of an unnamed namespace by the pid holding it
+    pub fn load_pid(pid: u32) -> Result<Self> {
+        match Self::read(&Path::new(STATE_DIR).join(format!("pid-{}", pid))) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} is not an ipvlan process", pid),
+            )),
+            result => result,
+        }
+    }
+
+    /// Loads all records
+    pub fn list() -> Result<Vec<Self>> {
+        let dir = match read_dir(STATE_DIR) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            dir => dir?,
+        };
+
+        let path = Path::new(STATE_DIR);
+        check(path, &path.symlink_metadata()?)?;
+
+        Ok(dir
+            .filter_map(|e| e.ok())
+            .filter_map(|e| Self::read(&e.path()).ok())
+            .collect())
+    }
+
+    /// Whether the namespace still exists
+    ///
+    /// The namespace is identified by its inode, which (unlike the pid) can't
+    /// be reused while anything refers to it.
+    pub fn alive(&self) -> bool {
+        let path = match &self.name {
+            Some(name) => Path::new(crate::netns::NETNS_DIR).join(name),
+            None => Path::new("/proc")
+                .join(self.pid.to_string())
+                .join("ns")
+                .join("net"),
+        };
+
+        // A reused pid can't be told apart from the original process, so a
+        // namespace which can't be inspected is assumed to still exist.
+        let metadata = caps::with(Capability::CAP_DAC_OVERRIDE, || std::fs::metadata(&path))
+            .or_else(|_| std::fs::metadata(&path));
+        match metadata {
+            Ok(md) => (md.dev(), md.ino()) == self.namespace,
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => true,
+            Err(..) => false,
+        }
+    }
+
+    /// Saves the record
+    pub fn save(&self) -> Result<()> {
+        directory(Path::new(STATE_DIR))?;
+        replace(&self.path()?, |file| {
+            serde_json::to_writer_pretty(file, self)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+        })
+    }
+
+    /// Removes the record, releasing its addresses
+    ///
+    /// The addresses are leased first, so that they are never free for
+    /// anyone to take in between.
+    pub fn remove(&self) -> Result<()> {
+        let addresses: Vec<IpAddr> = self
+            .links
+            .iter()
+            .flat_map(|l| l.addresses.iter().map(|a| a.address))
+            .collect();
+        crate::lease::release(self.uid, &addresses)?;
+
+        let path = self.path()?;
+        caps::with(Capability::CAP_DAC_OVERRIDE, || remove_file(&path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A record written before labels, programs and commands were recorded
+    fn record(name: Option<&str>, pid: u32) -> Record {
+        let mut record: Record = serde_json::from_str(
+            r#"{
+                "name": null,
+                "uid": 1000,
+                "pid": 0,
+                "argv": ["/bin/sh"],
+                "namespace": [0, 0],
+                "links": []
+            }"#,
+        )
+        .unwrap();
+
+        record.name = name.map(String::from);
+        record.pid = pid;
+        record
+    }
+
+    #[test]
+    fn defaults() {
+        let record = record(None, 0);
+        assert!(record.labels.is_empty());
+        assert!(record.programs.is_empty());
+        assert!(record.command.is_none());
+    }
+
+    #[test]
+    fn path() {
+        let path = record(None, 1234).path().unwrap();
+        assert_eq!(path, Path::new("/run/ipvlan/pid-1234"));
+
+        let path = record(Some("web"), 1234).path().unwrap();
+        assert_eq!(path, Path::new("/run/ipvlan/netns-web"));
+
+        for name in &["", ".", "..", "a/b", "../web"] {
+            let error = record(Some(name), 1234).path().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn alive() {
+        let md = std::fs::metadata("/proc/self/ns/net").unwrap();
+        let mut record = record(None, std::process::id());
+
+        record.namespace = (md.dev(), md.ino());
+        assert!(record.alive());
+
+        // The pid was reused by a process in another namespace.
+        record.namespace = (md.dev(), md.ino() + 1);
+        assert!(!record.alive());
+
+        // The named namespace is gone.
+        record.name = Some("ipvlan-test-missing".into());
+        assert!(!record.alive());
+    }
+}
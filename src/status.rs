@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reports on the ipvlan namespace of the current process
+
+use crate::netlink::{Address, Interface, Route, RT_TABLE_MAIN};
+use crate::state::Record;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::metadata;
+use std::io::{ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::os::unix::prelude::*;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// A route of the main table
+#[derive(Debug, Serialize)]
+struct RouteStatus {
+    destination: String,
+    kind: String,
+    gateway: Option<IpAddr>,
+    interface: Option<String>,
+    metric: Option<u32>,
+
+    /// Whether the gateway of a default route answers pings
+    reachable: Option<bool>,
+}
+
+/// The state of the current network namespace
+#[derive(Debug, Serialize)]
+struct Status {
+    namespace: (u64, u64),
+    name: Option<String>,
+    pid: Option<u32>,
+    labels: BTreeMap<String, String>,
+    interfaces: BTreeMap<String, Vec<String>>,
+    routes: Vec<RouteStatus>,
+}
+
+/// Sends an ICMP echo request to `address` and waits for a reply
+///
+/// This uses an unprivileged ping socket, so `None` is returned when
+/// `net.ipv4.ping_group_range` doesn't permit the user to create one.
+fn ping(address: IpAddr, timeout: Duration) -> Option<bool> {
+    let (family, protocol, kind) = match address {
+        IpAddr::V4(..) => (libc::AF_INET, libc::IPPROTO_ICMP, 8),
+        IpAddr::V6(..) => (libc::AF_INET6, libc::IPPROTO_ICMPV6, 128),
+    };
+
+    let fd = match unsafe { libc::socket(family, libc::SOCK_DGRAM, protocol) } {
+        -1 => return None,
+        fd => fd,
+    };
+
+    // The kernel fills in the identifier and checksum of ping sockets.
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    let request = [kind, 0, 0, 0, 0, 0, 0, 1];
+    let mut reply = [0u8; 64];
+
+    let result = socket
+        .set_read_timeout(Some(timeout))
+        .and_then(|()| socket.send_to(&request, SocketAddr::new(address, 0)))
+        .and_then(|_| socket.recv(&mut reply));
+
+    match result {
+        Ok(..) => Some(true),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => None,
+        Err(..) => Some(false),
+    }
+}
+
+/// Prints the status of the current network namespace
+pub fn status(json: bool) -> Result<()> {
+    let md = metadata("/proc/self/ns/net")?;
+    let namespace = (md.dev(), md.ino());
+    let record = Record::list()?
+        .into_iter()
+        .find(|r| r.namespace == namespace);
+
+    let mut interfaces = BTreeMap::<String, Vec<String>>::new();
+    for address in Address::list()? {
+        let subnet = address.subnet();
+        interfaces
+            .entry(address.interface()?.to_string())
+            .or_default()
+            .push(format!("{}/{}", address.address(), subnet.prefix()));
+    }
+
+    let names: HashMap<u32, String> = Interface::list()?
+        .iter()
+        .map(|i| (i.index(), i.name().to_string()))
+        .collect();
+
+    let mut routes = Vec::new();
+    for route in Route::list()? {
+        if route.table() != u32::from(RT_TABLE_MAIN) {
+            continue;
+        }
+
+        // Only the gateways of installed default routes are pinged.
+        let destination = route.destination();
+        let reachable = match route.gateway() {
+            Some(gateway) if destination.prefix() == 0 => ping(gateway, Duration::from_secs(1)),
+            _ => None,
+        };
+
+        routes.push(RouteStatus {
+            destination: match destination.prefix() {
+                0 => "default".into(),
+                _ => destination.to_string(),
+            },
+            kind: route.kind().to_string(),
+            gateway: route.gateway(),
+            interface: route.interface().and_then(|i| names.get(&i).cloned()),
+            metric: route.metric(),
+            reachable,
+        });
+    }
+
+    let status = Status {
+        namespace,
+        name: record.as_ref().and_then(|r| r.name.clone()),
+        pid: record.as_ref().map(|r| r.pid),
+        labels: record.map(|r| r.labels).unwrap_or_default(),
+        interfaces,
+        routes,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    match (&status.name, status.pid) {
+        (Some(name), _) => println!("namespace {} (ipvlan {})", namespace.1, name),
+        (None, Some(pid)) => println!("namespace {} (ipvlan pid {})", namespace.1, pid),
+        (None, None) => println!("namespace {} (not created by ipvlan)", namespace.1),
+    }
+
+    for (key, value) in &status.labels {
+        println!("label {}={}", key, value);
+    }
+
+    for (interface, addresses) in &status.interfaces {
+        println!("{}", interface);
+        for address in addresses {
+            println!("    address {}", address);
+        }
+    }
+
+    for route in &status.routes {
+        let mut line = match route.kind.as_str() {
+            "unicast" => format!("route {}", route.destination),
+            kind => format!("route {} {}", kind, route.destination),
+        };
+
+        if let Some(gateway) = route.gateway {
+            line += &format!(" via {}", gateway);
+        }
+
+        if let Some(interface) = &route.interface {
+            line += &format!(" dev {}", interface);
+        }
+
+        if let Some(metric) = route.metric {
+            line += &format!(" metric {}", metric);
+        }
+
+        match (route.reachable, route.gateway) {
+            (Some(true), _) => line += " (reachable)",
+            (Some(false), _) => line += " (unreachable)",
+            (None, Some(..)) if route.destination == "default" => line += " (unknown)",
+            (None, _) => (),
+        }
+
+        println!("{}", line);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Network sysctl settings applied within the namespace
+//!
+//! Only the `net.` settings are accepted: the others aren't specific to the
+//! network namespace, so setting them would affect the host.
+
+use std::io::Result;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::debug;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sysctl {
+    key: String,
+    value: String,
+}
+
+impl std::fmt::Display for Sysctl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+impl FromStr for Sysctl {
+    type Err = String;
+
+    /// Parses `KEY=VALUE`, such as `net.ipv4.ip_forward=1`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut split = s.splitn(2, '=');
+        let (key, value) = match (split.next(), split.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => return Err(format!("invalid sysctl: {}", s)),
+        };
+
+        let allowed = |c: char| c.is_ascii_alphanumeric() || "_-".contains(c);
+        let names: Vec<&str> = key.split('.').collect();
+        let valid = names
+            .iter()
+            .all(|n| !n.is_empty() && n.chars().all(allowed));
+        if names.len() < 2 || names[0] != "net" || !valid {
+            return Err(format!("invalid network sysctl: {}", key));
+        }
+
+        Ok(Self {
+            key: key.into(),
+            value: value.into(),
+        })
+    }
+}
+
+impl Sysctl {
+    #[inline]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Sets the value in the current network namespace
+    pub fn apply(&self) -> Result<()> {
+        let path: PathBuf = ["/proc/sys"]
+            .iter()
+            .copied()
+            .chain(self.key.split('.'))
+            .collect();
+        debug!(
+            "setting sysctl: path={} value={}",
+            path.display(),
+            self.value
+        );
+        std::fs::write(path, &self.value)
+    }
+}
@@ -0,0 +1,10 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers shared by the unit tests
+
+use std::net::IpAddr;
+
+/// Parses an address which is known to be valid
+pub fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Switching the executed command to another user
+
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+
+use caps::Capability;
+
+fn cstring(name: &str) -> Result<CString> {
+    CString::new(name).map_err(|_| ErrorKind::InvalidInput.into())
+}
+
+/// Looks up the uid and primary gid of a user
+fn passwd(name: &str) -> Result<(u32, u32)> {
+    let cname = cstring(name)?;
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let mut result = std::ptr::null_mut();
+
+    match unsafe {
+        libc::getpwnam_r(
+            cname.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    } {
+        0 if result.is_null() => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("unknown user: {}", name),
+        )),
+        0 => Ok((pwd.pw_uid, pwd.pw_gid)),
+        e => Err(Error::from_raw_os_error(e)),
+    }
+}
+
+/// Looks up the uid of a user name (or parses a numeric uid)
+pub fn uid(name: &str) -> Result<u32> {
+    match name.parse() {
+        Ok(uid) => Ok(uid),
+        Err(..) => Ok(passwd(name)?.0),
+    }
+}
+
+/// Looks up the gid of a group name (or parses a numeric gid)
+pub fn group(name: &str) -> Result<u32> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+
+    let cname = cstring(name)?;
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let mut result = std::ptr::null_mut();
+
+    match unsafe {
+        libc::getgrnam_r(
+            cname.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    } {
+        0 if result.is_null() => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("unknown group: {}", name),
+        )),
+        0 => Ok(grp.gr_gid),
+        e => Err(Error::from_raw_os_error(e)),
+    }
+}
+
+/// Looks up the supplementary groups of a user
+fn grouplist(name: &str, gid: u32) -> Result<Vec<u32>> {
+    let cname = cstring(name)?;
+    let mut groups = vec![0; 64];
+
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        match unsafe { libc::getgrouplist(cname.as_ptr(), gid, groups.as_mut_ptr(), &mut count) } {
+            -1 => groups.resize(count as usize, 0),
+            _ => {
+                groups.truncate(count as usize);
+                return Ok(groups);
+            }
+        }
+    }
+}
+
+/// Returns the real gid and supplementary groups of the current process
+pub fn groups() -> Result<Vec<u32>> {
+    let count = match unsafe { libc::getgroups(0, std::ptr::null_mut()) } {
+        -1 => return Err(Error::last_os_error()),
+        count => count,
+    };
+
+    let mut groups = vec![0; count as usize];
+    match unsafe { libc::getgroups(count, groups.as_mut_ptr()) } {
+        -1 => return Err(Error::last_os_error()),
+        count => groups.truncate(count as usize),
+    }
+
+    groups.push(unsafe { libc::getgid() });
+    Ok(groups)
+}
+
+/// The identity to execute the command as
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>,
+}
+
+impl Identity {
+    /// Resolves an identity from a user name and explicit overrides
+    ///
+    /// Without a user name, the gid defaults to the current real gid and
+    /// the supplementary groups default to none.
+    pub fn resolve(
+        user: Option<&str>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        groups: &[String],
+    ) -> Result<Option<Self>> {
+        if user.is_none() && uid.is_none() && gid.is_none() && groups.is_empty() {
+            return Ok(None);
+        }
+
+        let mut identity = match user {
+            Some(user) => {
+                let (uid, gid) = passwd(user)?;
+                let groups = grouplist(user, gid)?;
+                Self { uid, gid, groups }
+            }
+
+            None => Self {
+                uid: unsafe { libc::getuid() },
+                gid: unsafe { libc::getgid() },
+                groups: Vec::new(),
+            },
+        };
+
+        identity.uid = uid.unwrap_or(identity.uid);
+        identity.gid = gid.unwrap_or(identity.gid);
+        if !groups.is_empty() {
+            identity.groups = groups.iter().map(|g| group(g)).collect::<Result<_>>()?;
+        }
+
+        Ok(Some(identity))
+    }
+
+    /// Switches the current process to this identity
+    pub fn apply(&self) -> Result<()> {
+        caps::with(Capability::CAP_SETGID, || {
+            if unsafe { libc::setgroups(self.groups.len(), self.groups.as_ptr()) } != 0 {
+                return Err(Error::last_os_error());
+            }
+
+            match unsafe { libc::setresgid(self.gid, self.gid, self.gid) } {
+                -1 => Err(Error::last_os_error()),
+                0 => Ok(()),
+                _ => unreachable!(),
+            }
+        })?;
+
+        caps::with(Capability::CAP_SETUID, || {
+            match unsafe { libc::setresuid(self.uid, self.uid, self.uid) } {
+                -1 => Err(Error::last_os_error()),
+                0 => Ok(()),
+                _ => unreachable!(),
+            }
+        })
+    }
+}